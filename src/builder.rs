@@ -0,0 +1,248 @@
+use crate::{
+    lazy_atomic_cell::LazyAtomicCell,
+    policy::{ClassPolicy, PolicyRules},
+    ShuffleRng, ShuffleStrategy, ShufflingAllocator, SizeClassTable, SplitMix64,
+    SwapOnAllocAndFree,
+};
+use std::{alloc::GlobalAlloc, marker::PhantomData, ops::RangeInclusive, sync::atomic::AtomicBool};
+
+/// The configuration chosen when building a `ShufflingAllocator`.
+///
+/// Environment variables may still override parts of this when the allocator's
+/// state is initialized.
+#[derive(Clone, Copy)]
+pub(crate) struct Config {
+    pub seed: Option<u64>,
+    pub array_size: usize,
+    pub enabled: bool,
+    pub size_classes: &'static SizeClassTable,
+    pub policies: PolicyRules,
+    pub large_object_pages: usize,
+    pub thread_local_arrays: bool,
+    pub per_cpu_arrays: bool,
+}
+
+/// A builder for configuring a [`ShufflingAllocator`].
+///
+/// All of the builder's methods are `const fn`s, so it can be used to
+/// initialize a `static` global allocator.
+///
+/// Created with
+/// [`ShufflingAllocator::builder`](./struct.ShufflingAllocator.html#method.builder).
+///
+/// # Example
+///
+/// ```
+/// use shuffling_allocator::ShufflingAllocator;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System, 1024> =
+///     ShufflingAllocator::builder(&System)
+///         .seed(0x1234_5678)
+///         .array_size(512)
+///         .build();
+/// ```
+pub struct ShufflingAllocatorBuilder<
+    A,
+    const N: usize = 256,
+    R = SplitMix64,
+    S = SwapOnAllocAndFree,
+> where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
+{
+    inner: &'static A,
+    config: Config,
+    rng: PhantomData<fn() -> R>,
+    strategy: PhantomData<fn() -> S>,
+}
+
+impl<A, const N: usize, R, S> ShufflingAllocatorBuilder<A, N, R, S>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
+{
+    pub(crate) const fn new(inner: &'static A) -> Self {
+        ShufflingAllocatorBuilder {
+            inner,
+            config: Config {
+                seed: None,
+                array_size: N,
+                enabled: true,
+                size_classes: &SizeClassTable::DEFAULT,
+                policies: PolicyRules::new(),
+                large_object_pages: 0,
+                thread_local_arrays: false,
+                per_cpu_arrays: false,
+            },
+            rng: PhantomData,
+            strategy: PhantomData,
+        }
+    }
+
+    /// Use `seed` for the allocator's shuffling decisions, rather than a seed
+    /// chosen from the operating system's entropy source.
+    ///
+    /// The `SHUFFLING_ALLOCATOR_SEED` environment variable takes precedence
+    /// over this.
+    pub const fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Use only the first `size` entries of each shuffling array.
+    ///
+    /// This allows choosing a smaller randomization window than `N` without
+    /// changing the allocator's type. The `SHUFFLING_ALLOCATOR_ARRAY_SIZE`
+    /// environment variable takes precedence over this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero or greater than `N`.
+    pub const fn array_size(mut self, size: usize) -> Self {
+        assert!(
+            size > 0 && size <= N,
+            "array size must be between 1 and the allocator's `N`"
+        );
+        self.config.array_size = size;
+        self
+    }
+
+    /// Whether to shuffle allocations at all.
+    ///
+    /// When disabled, every allocation is passed straight through to the inner
+    /// allocator. Shuffling is enabled by default, and setting the
+    /// `SHUFFLING_ALLOCATOR_DISABLE` environment variable disables it
+    /// regardless of this. To turn shuffling on and off while the program is
+    /// running instead, see
+    /// [`ShufflingAllocator::set_enabled`](./struct.ShufflingAllocator.html#method.set_enabled).
+    pub const fn enabled(mut self, enabled: bool) -> Self {
+        self.config.enabled = enabled;
+        self
+    }
+
+    /// Use the given size classes, rather than
+    /// [`SizeClassTable::DEFAULT`](./struct.SizeClassTable.html#associatedconstant.DEFAULT).
+    pub const fn size_classes(mut self, size_classes: &'static SizeClassTable) -> Self {
+        self.config.size_classes = size_classes;
+        self
+    }
+
+    /// Handle the size classes with sizes in `sizes` according to `policy`,
+    /// rather than shuffling them all alike.
+    ///
+    /// When policies for overlapping ranges are given, the last one wins. Up to
+    /// 16 policies may be given.
+    ///
+    /// # Panics
+    ///
+    /// Panics if too many policies are given, or if `policy` is a `Window`
+    /// that is zero or greater than `N`.
+    ///
+    /// # Example
+    ///
+    /// Only shuffle the 32 to 128 byte size classes, and shuffle the 64 byte
+    /// class more thoroughly than the rest:
+    ///
+    /// ```
+    /// use shuffling_allocator::{ClassPolicy, ShufflingAllocator};
+    /// use std::alloc::System;
+    ///
+    /// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System, 1024> =
+    ///     ShufflingAllocator::builder(&System)
+    ///         .array_size(256)
+    ///         .size_class_policy(0..=usize::MAX, ClassPolicy::PassThrough)
+    ///         .size_class_policy(32..=128, ClassPolicy::Shuffle)
+    ///         .size_class_policy(64..=64, ClassPolicy::Window(1024))
+    ///         .build();
+    /// ```
+    pub const fn size_class_policy(
+        mut self,
+        sizes: RangeInclusive<usize>,
+        policy: ClassPolicy,
+    ) -> Self {
+        if let ClassPolicy::Window(n) = policy {
+            assert!(
+                n > 0 && n <= N,
+                "window size must be between 1 and the allocator's `N`"
+            );
+        }
+        self.config.policies = self.config.policies.with(sizes, policy);
+        self
+    }
+
+    /// Randomize the placement of allocations larger than the largest size
+    /// class, by placing each one up to `pages` 4 KiB pages into a block that
+    /// is over-allocated from the inner allocator by that much.
+    ///
+    /// This randomizes large objects' addresses at page granularity, at the
+    /// cost of `pages` pages of address space for each one. The padding is
+    /// never touched, so when the inner allocator maps large blocks directly
+    /// from the operating system, as most do, it doesn't cost physical memory.
+    ///
+    /// Zero, the default, disables this, and passes large allocations straight
+    /// through to the inner allocator. The `SHUFFLING_ALLOCATOR_LARGE_PAGES`
+    /// environment variable takes precedence over this.
+    pub const fn large_object_pages(mut self, pages: usize) -> Self {
+        self.config.large_object_pages = pages;
+        self
+    }
+
+    /// Give each thread its own shuffling arrays, rather than sharing one set
+    /// between all threads.
+    ///
+    /// With shared arrays, an object freed on one thread is often handed out on
+    /// another, which defeats the per-thread caches of allocators like jemalloc
+    /// and mimalloc. Per-thread arrays keep objects on the thread that
+    /// allocated them, as the inner allocator would, and are returned to the
+    /// inner allocator when their thread exits. The cost is a set of arrays
+    /// for every thread that allocates.
+    ///
+    /// Each thread also gets its own random number streams, derived from the
+    /// seed and the thread's name. Threads with the same name are numbered in
+    /// the order that they first allocate. So, with a fixed seed, a thread
+    /// with a unique name that makes the same sequence of allocations gets the
+    /// same layout from run to run, no matter how it is interleaved with other
+    /// threads.
+    ///
+    /// The shared arrays are still used while a thread's own arrays are being
+    /// created or torn down, and by any allocator beyond the first four with
+    /// this enabled that a thread uses. A thread's arrays for an allocator that
+    /// has been dropped are kept, and count towards those four, until the
+    /// thread exits.
+    pub const fn thread_local_arrays(mut self, enabled: bool) -> Self {
+        self.config.thread_local_arrays = enabled;
+        self
+    }
+
+    /// Give each CPU its own shuffling arrays, rather than sharing one set
+    /// between all threads.
+    ///
+    /// Like [`thread_local_arrays`](#method.thread_local_arrays), this cuts
+    /// down on objects moving between threads and on contention for the
+    /// arrays, but its memory overhead grows with the number of CPUs rather
+    /// than the number of threads, which is better for programs that run many
+    /// threads. Arrays are never freed, and CPUs beyond the 256th share arrays
+    /// with lower-numbered CPUs.
+    ///
+    /// This is only supported on Linux; elsewhere, the shared arrays are used
+    /// instead. If per-thread arrays are enabled as well, they take precedence.
+    pub const fn per_cpu_arrays(mut self, enabled: bool) -> Self {
+        self.config.per_cpu_arrays = enabled;
+        self
+    }
+
+    /// Build the configured `ShufflingAllocator`.
+    pub const fn build(self) -> ShufflingAllocator<A, N, R, S> {
+        ShufflingAllocator {
+            inner: self.inner,
+            config: self.config,
+            shuffling: AtomicBool::new(true),
+            state: LazyAtomicCell::new(self.inner),
+            strategy: PhantomData,
+        }
+    }
+}
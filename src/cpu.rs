@@ -0,0 +1,27 @@
+//! Finding out which CPU the current thread is running on.
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        /// Get the index of the CPU that the current thread is running on.
+        ///
+        /// With glibc 2.35 or later, this reads the CPU index out of the
+        /// thread's restartable sequence area, rather than making a system
+        /// call. The thread may be migrated to another CPU at any point, so
+        /// the answer is only a hint.
+        #[inline]
+        pub(crate) fn current() -> Option<usize> {
+            let cpu = unsafe { libc::sched_getcpu() };
+            if cpu < 0 {
+                None
+            } else {
+                Some(cpu as usize)
+            }
+        }
+    } else {
+        /// Per-CPU arrays are only supported on Linux.
+        #[inline]
+        pub(crate) fn current() -> Option<usize> {
+            None
+        }
+    }
+}
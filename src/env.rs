@@ -0,0 +1,98 @@
+//! Reading configuration from environment variables.
+//!
+//! This happens while the allocator is initializing itself, so none of it may
+//! allocate: we can't use `std::env`, and values are parsed directly out of
+//! the environment (or a stack buffer) instead.
+
+use std::str;
+
+/// Configuration overrides read from the environment.
+#[derive(Default)]
+pub(crate) struct EnvConfig {
+    /// `SHUFFLING_ALLOCATOR_SEED`
+    pub seed: Option<u64>,
+    /// `SHUFFLING_ALLOCATOR_DISABLE`
+    pub disable: bool,
+    /// `SHUFFLING_ALLOCATOR_ARRAY_SIZE`
+    pub array_size: Option<usize>,
+    /// `SHUFFLING_ALLOCATOR_LARGE_PAGES`
+    pub large_object_pages: Option<usize>,
+}
+
+impl EnvConfig {
+    pub fn read() -> Self {
+        let mut config = EnvConfig::default();
+        let mut buf = [0; 64];
+        if let Some(val) = getenv(b"SHUFFLING_ALLOCATOR_SEED\0", &mut buf) {
+            config.seed = parse_u64(val);
+        }
+        if let Some(val) = getenv(b"SHUFFLING_ALLOCATOR_DISABLE\0", &mut buf) {
+            config.disable = parse_bool(val);
+        }
+        if let Some(val) = getenv(b"SHUFFLING_ALLOCATOR_ARRAY_SIZE\0", &mut buf) {
+            config.array_size = parse_u64(val).map(|n| n as usize);
+        }
+        if let Some(val) = getenv(b"SHUFFLING_ALLOCATOR_LARGE_PAGES\0", &mut buf) {
+            config.large_object_pages = parse_u64(val).map(|n| n as usize);
+        }
+        config
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal integer.
+fn parse_u64(val: &[u8]) -> Option<u64> {
+    let val = str::from_utf8(val).ok()?.trim();
+    match val.strip_prefix("0x").or_else(|| val.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => val.parse().ok(),
+    }
+}
+
+/// Any value other than empty, `0`, or `false` counts as set.
+fn parse_bool(val: &[u8]) -> bool {
+    match str::from_utf8(val).map(str::trim) {
+        Ok("") | Ok("0") => false,
+        Ok(s) => !s.eq_ignore_ascii_case("false"),
+        Err(_) => true,
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+        /// Get the value of the nul-terminated environment variable `name`.
+        fn getenv<'a>(name: &[u8], _buf: &'a mut [u8]) -> Option<&'a [u8]> {
+            debug_assert_eq!(name.last(), Some(&0));
+            unsafe {
+                let val = libc::getenv(name.as_ptr().cast());
+                if val.is_null() {
+                    None
+                } else {
+                    Some(std::ffi::CStr::from_ptr(val).to_bytes())
+                }
+            }
+        }
+    } else if #[cfg(windows)] {
+        /// Get the value of the nul-terminated environment variable `name`,
+        /// copying it into `buf`.
+        fn getenv<'a>(name: &[u8], buf: &'a mut [u8]) -> Option<&'a [u8]> {
+            use winapi::um::processenv::GetEnvironmentVariableA;
+
+            debug_assert_eq!(name.last(), Some(&0));
+            let len = unsafe {
+                GetEnvironmentVariableA(
+                    name.as_ptr().cast(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len() as u32,
+                )
+            } as usize;
+
+            // Zero means the variable is unset, and a length that doesn't fit
+            // means it is too long to be a value we would accept anyways.
+            if len == 0 || len >= buf.len() {
+                None
+            } else {
+                Some(&buf[..len])
+            }
+        }
+    }
+}
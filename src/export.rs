@@ -0,0 +1,391 @@
+//! Writing statistics out as JSON, CSV, or Prometheus text, and dumping them
+//! to a file when the process exits.
+//!
+//! Everything is written straight to the writer with `write!`, which formats
+//! numbers on the stack, so serializing never allocates by itself. Dumping at
+//! exit opens and buffers the file inside an `unshuffled` scope, so the
+//! allocations that takes aren't shuffled.
+
+use crate::{
+    scoped, ClassStats, PhaseStats, ShuffleRng, ShuffleStrategy, ShufflingAllocator, Stats,
+};
+use std::{
+    alloc::GlobalAlloc,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, Once},
+};
+
+/// A format for writing statistics in.
+///
+/// See [`Stats::write`](./struct.Stats.html#method.write).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// A single JSON object, with the allocator-wide statistics as fields, and
+    /// `classes` and `phases` arrays of objects.
+    Json,
+    /// CSV with a header row and then one `kind,key,metric,value` row per
+    /// statistic, where `kind` is `total`, `class`, or `phase`, and `key` is
+    /// empty, the size class in bytes, or the phase's name in double quotes,
+    /// respectively.
+    Csv,
+    /// The Prometheus text exposition format, with every metric prefixed by
+    /// `shuffling_allocator_`, and `size` and `phase` labels on the per-class
+    /// and per-phase metrics.
+    Prometheus,
+}
+
+/// A statistic's name, and how to describe it.
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+    /// Whether the statistic only ever goes up, rather than being a gauge.
+    counter: bool,
+}
+
+const fn counter(name: &'static str, help: &'static str) -> Metric {
+    Metric {
+        name,
+        help,
+        counter: true,
+    }
+}
+
+const fn gauge(name: &'static str, help: &'static str) -> Metric {
+    Metric {
+        name,
+        help,
+        counter: false,
+    }
+}
+
+const TOTALS: [Metric; 8] = [
+    counter(
+        "bypassed_size",
+        "Allocations not shuffled because they are too large.",
+    ),
+    counter(
+        "bypassed_align",
+        "Allocations not shuffled because they are over-aligned.",
+    ),
+    counter(
+        "unshuffled",
+        "Allocations not shuffled because shuffling was off.",
+    ),
+    gauge("live_bytes", "Bytes currently allocated."),
+    gauge("peak_bytes", "The most bytes ever allocated at once."),
+    gauge(
+        "parked_bytes",
+        "Bytes of objects parked in shuffling arrays.",
+    ),
+    counter(
+        "rng_lock_contended",
+        "Times a thread waited for a random number generator's lock.",
+    ),
+    counter(
+        "rng_lock_wait_nanos",
+        "Nanoseconds spent waiting for random number generators' locks.",
+    ),
+];
+
+const CLASS: [Metric; 3] = [
+    counter("class_allocs", "Shuffled allocations in a size class."),
+    counter("class_frees", "Shuffled frees in a size class."),
+    gauge("class_parked", "Objects parked in a size class's arrays."),
+];
+
+const PHASE: [Metric; 6] = [
+    counter("phase_allocs", "Allocations during a phase."),
+    counter("phase_frees", "Frees during a phase."),
+    counter("phase_bypassed", "Allocations not shuffled during a phase."),
+    counter("phase_bytes_allocated", "Bytes allocated during a phase."),
+    counter("phase_bytes_freed", "Bytes freed during a phase."),
+    counter(
+        "phase_decisions",
+        "Random shuffling decisions made during a phase.",
+    ),
+];
+
+fn totals(stats: &Stats) -> [u64; 8] {
+    [
+        stats.bypassed_size,
+        stats.bypassed_align,
+        stats.unshuffled,
+        stats.live_bytes as u64,
+        stats.peak_bytes as u64,
+        stats.parked_bytes() as u64,
+        stats.rng_lock.contended,
+        stats.rng_lock.wait_nanos,
+    ]
+}
+
+fn class(class: &ClassStats) -> [u64; 3] {
+    [class.allocs, class.frees, class.parked as u64]
+}
+
+fn phase(phase: &PhaseStats) -> [u64; 6] {
+    [
+        phase.allocs,
+        phase.frees,
+        phase.bypassed,
+        phase.bytes_allocated,
+        phase.bytes_freed,
+        phase.decisions,
+    ]
+}
+
+/// The name of a per-class or per-phase metric, without its prefix.
+fn field(metric: &Metric) -> &'static str {
+    let name = metric.name;
+    name.strip_prefix("class_")
+        .or_else(|| name.strip_prefix("phase_"))
+        .unwrap_or(name)
+}
+
+impl Stats {
+    /// Write these statistics to `w` in the given format.
+    ///
+    /// Writing doesn't allocate, other than whatever `w` itself does.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::{ShufflingAllocator, StatsFormat};
+    /// use std::alloc::System;
+    ///
+    /// #[global_allocator]
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// let boxes: Vec<_> = (0..100).map(Box::new).collect();
+    /// SHUFFLED
+    ///     .stats()
+    ///     .write(StatsFormat::Prometheus, std::io::stdout().lock())
+    ///     .unwrap();
+    /// # drop(boxes);
+    /// ```
+    pub fn write(&self, format: StatsFormat, w: impl Write) -> io::Result<()> {
+        match format {
+            StatsFormat::Json => self.write_json(w),
+            StatsFormat::Csv => self.write_csv(w),
+            StatsFormat::Prometheus => self.write_prometheus(w),
+        }
+    }
+
+    /// Write these statistics to `w` as JSON.
+    ///
+    /// See [`StatsFormat::Json`](./enum.StatsFormat.html#variant.Json).
+    pub fn write_json(&self, mut w: impl Write) -> io::Result<()> {
+        write!(w, "{{")?;
+        for (metric, value) in TOTALS.iter().zip(totals(self)) {
+            write!(w, "\"{}\":{},", metric.name, value)?;
+        }
+
+        write!(w, "\"classes\":[")?;
+        for (i, c) in self.classes().iter().enumerate() {
+            let comma = if i == 0 { "" } else { "," };
+            write!(w, "{}{{\"size\":{}", comma, c.size)?;
+            for (metric, value) in CLASS.iter().zip(class(c)) {
+                write!(w, ",\"{}\":{}", field(metric), value)?;
+            }
+            write!(w, "}}")?;
+        }
+
+        write!(w, "],\"phases\":[")?;
+        for (i, p) in self.phases().iter().enumerate() {
+            let comma = if i == 0 { "" } else { "," };
+            write!(w, "{}{{\"name\":\"", comma)?;
+            write_name(&mut w, p.name, StatsFormat::Json)?;
+            write!(w, "\"")?;
+            for (metric, value) in PHASE.iter().zip(phase(p)) {
+                write!(w, ",\"{}\":{}", field(metric), value)?;
+            }
+            write!(w, "}}")?;
+        }
+        writeln!(w, "]}}")
+    }
+
+    /// Write these statistics to `w` as CSV.
+    ///
+    /// See [`StatsFormat::Csv`](./enum.StatsFormat.html#variant.Csv).
+    pub fn write_csv(&self, mut w: impl Write) -> io::Result<()> {
+        writeln!(w, "kind,key,metric,value")?;
+        for (metric, value) in TOTALS.iter().zip(totals(self)) {
+            writeln!(w, "total,,{},{}", metric.name, value)?;
+        }
+        for c in self.classes() {
+            for (metric, value) in CLASS.iter().zip(class(c)) {
+                writeln!(w, "class,{},{},{}", c.size, field(metric), value)?;
+            }
+        }
+        for p in self.phases() {
+            for (metric, value) in PHASE.iter().zip(phase(p)) {
+                write!(w, "phase,\"")?;
+                write_name(&mut w, p.name, StatsFormat::Csv)?;
+                writeln!(w, "\",{},{}", field(metric), value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write these statistics to `w` in the Prometheus text exposition
+    /// format.
+    ///
+    /// See [`StatsFormat::Prometheus`](./enum.StatsFormat.html#variant.Prometheus).
+    pub fn write_prometheus(&self, mut w: impl Write) -> io::Result<()> {
+        for (metric, value) in TOTALS.iter().zip(totals(self)) {
+            write_help(&mut w, metric)?;
+            writeln!(w, "{} {}", Name(metric), value)?;
+        }
+        for (i, metric) in CLASS.iter().enumerate() {
+            write_help(&mut w, metric)?;
+            for c in self.classes() {
+                writeln!(w, "{}{{size=\"{}\"}} {}", Name(metric), c.size, class(c)[i])?;
+            }
+        }
+        for (i, metric) in PHASE.iter().enumerate() {
+            write_help(&mut w, metric)?;
+            for p in self.phases() {
+                write!(w, "{}{{phase=\"", Name(metric))?;
+                write_name(&mut w, p.name, StatsFormat::Prometheus)?;
+                writeln!(w, "\"}} {}", phase(p)[i])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A metric's full Prometheus name.
+struct Name<'a>(&'a Metric);
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = if self.0.counter { "_total" } else { "" };
+        write!(f, "shuffling_allocator_{}{}", self.0.name, suffix)
+    }
+}
+
+/// Write the Prometheus `HELP` and `TYPE` lines for `metric`.
+fn write_help(w: &mut impl Write, metric: &Metric) -> io::Result<()> {
+    let kind = if metric.counter { "counter" } else { "gauge" };
+    writeln!(w, "# HELP {} {}", Name(metric), metric.help)?;
+    writeln!(w, "# TYPE {} {}", Name(metric), kind)
+}
+
+/// Write a phase's name, escaped to go between double quotes in the given
+/// format.
+fn write_name(w: &mut impl Write, name: &str, format: StatsFormat) -> io::Result<()> {
+    for c in name.chars() {
+        match (format, c) {
+            (StatsFormat::Csv, '"') => write!(w, "\"\"")?,
+            (StatsFormat::Csv, c) => write!(w, "{}", c)?,
+            (_, '"') | (_, '\\') => write!(w, "\\{}", c)?,
+            (_, '\n') => write!(w, "\\n")?,
+            (StatsFormat::Json, c) if c < ' ' => write!(w, "\\u{:04x}", c as u32)?,
+            (_, c) => write!(w, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Something whose statistics can be dumped at exit.
+trait StatsSource: Sync {
+    fn stats(&self) -> Stats;
+}
+
+impl<A, const N: usize, R, S> StatsSource for ShufflingAllocator<A, N, R, S>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
+    Self: Sync,
+{
+    fn stats(&self) -> Stats {
+        ShufflingAllocator::stats(self)
+    }
+}
+
+/// A file to write an allocator's statistics to when the process exits.
+type Dump = (&'static dyn StatsSource, PathBuf, StatsFormat);
+
+static DUMPS: Mutex<Vec<Dump>> = Mutex::new(Vec::new());
+
+impl<A, const N: usize, R, S> ShufflingAllocator<A, N, R, S>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
+    Self: Sync,
+{
+    /// Write this allocator's statistics to the file at `path`, in the given
+    /// format, when the process exits.
+    ///
+    /// This is only available with the `stats` cargo feature. The statistics
+    /// are written from an `atexit` handler, so they are written when `main`
+    /// returns or `std::process::exit` is called, but not when the process is
+    /// killed or aborts. The file is created, or truncated if it exists, and
+    /// shuffling is turned off on the exiting thread while it is written.
+    /// Errors are reported on standard error. Calling this more than once
+    /// writes more than one file.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use shuffling_allocator::{ShufflingAllocator, StatsFormat};
+    /// use std::alloc::System;
+    ///
+    /// #[global_allocator]
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// fn main() {
+    ///     SHUFFLED.dump_stats_at_exit("allocator-stats.json", StatsFormat::Json);
+    ///     // ...
+    /// }
+    /// ```
+    pub fn dump_stats_at_exit(&'static self, path: impl Into<PathBuf>, format: StatsFormat) {
+        static REGISTER: Once = Once::new();
+
+        let path = path.into();
+        lock_dumps().push((self, path, format));
+        REGISTER.call_once(|| unsafe {
+            atexit(dump_all);
+        });
+    }
+}
+
+fn lock_dumps() -> MutexGuard<'static, Vec<Dump>> {
+    DUMPS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+extern "C" fn dump_all() {
+    scoped::unshuffled(|| {
+        for (source, path, format) in lock_dumps().iter() {
+            let stats = source.stats();
+            if let Err(e) = dump(&stats, path, *format) {
+                let _ = writeln!(
+                    io::stderr(),
+                    "shuffling-allocator: failed to write statistics to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    })
+}
+
+fn dump(stats: &Stats, path: &Path, format: StatsFormat) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    stats.write(format, &mut w)?;
+    w.flush()
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+        use libc::atexit;
+    } else {
+        extern "C" {
+            fn atexit(f: extern "C" fn()) -> std::os::raw::c_int;
+        }
+    }
+}
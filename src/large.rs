@@ -0,0 +1,89 @@
+//! Randomizing the placement of allocations that are too large for any size
+//! class.
+//!
+//! Keeping shuffling arrays of large objects would park far too much memory,
+//! so instead each large allocation is over-allocated by some number of pages
+//! of padding, and the object is placed a random number of whole pages into
+//! that block. The padding is never touched, so for blocks that the inner
+//! allocator maps directly from the operating system it costs address space
+//! but not physical memory.
+//!
+//! The object's offset into its block is recorded in a header word just past
+//! the end of the object, so that it can be found again on deallocation:
+//!
+//! ```text
+//! block                 ptr
+//! |                     |
+//! V                     V
+//! +---------------------+--------+--------+---------------------+
+//! | offset = k pages    | object | header | (pages - k) pages   |
+//! +---------------------+--------+--------+---------------------+
+//! ```
+
+use std::{alloc::Layout, mem};
+
+/// The granularity at which large allocations are placed.
+pub(crate) const PAGE_SIZE: usize = 4096;
+
+/// Get the layout of the block backing a large allocation of `layout`, padded
+/// with `pages` pages.
+///
+/// Returns `None` if the block would be too large, or the allocation is too
+/// aligned to be placed at page granularity.
+#[inline]
+pub(crate) fn block_layout(layout: Layout, pages: usize) -> Option<Layout> {
+    if layout.align() > PAGE_SIZE {
+        return None;
+    }
+    let size = header_offset(layout.size())?
+        .checked_add(mem::size_of::<usize>())?
+        .checked_add(pages.checked_mul(PAGE_SIZE)?)?;
+    Layout::from_size_align(size, layout.align().max(mem::align_of::<usize>())).ok()
+}
+
+/// Place an object of `size` bytes `offset` bytes into `block`, returning the
+/// pointer to the object.
+///
+/// # Safety
+///
+/// `block` must have been allocated with the `block_layout` for the object's
+/// layout, and `offset` must be a multiple of `PAGE_SIZE` no larger than the
+/// padding.
+#[inline]
+pub(crate) unsafe fn place(block: *mut u8, size: usize, offset: usize) -> *mut u8 {
+    debug_assert_eq!(offset % PAGE_SIZE, 0);
+    let ptr = block.add(offset);
+    write_header(ptr, size, offset);
+    ptr
+}
+
+/// Record the offset of the object at `ptr` into its block.
+///
+/// # Safety
+///
+/// `ptr` must be a large object of `size` bytes that was placed `offset`
+/// bytes into a block allocated with the `block_layout` for the object's
+/// layout.
+#[inline]
+pub(crate) unsafe fn write_header(ptr: *mut u8, size: usize, offset: usize) {
+    let header = ptr.add(header_offset(size).unwrap()).cast::<usize>();
+    header.write(offset);
+}
+
+/// Get the offset of the large object at `ptr`, which is `size` bytes, into
+/// its block.
+///
+/// # Safety
+///
+/// `ptr` must be a large object of `size` bytes that was placed by `place`.
+#[inline]
+pub(crate) unsafe fn offset(ptr: *mut u8, size: usize) -> usize {
+    let header = ptr.add(header_offset(size).unwrap()).cast::<usize>();
+    header.read()
+}
+
+/// The header lives in the first word-aligned word after the object.
+#[inline]
+fn header_offset(size: usize) -> Option<usize> {
+    Some(size.checked_add(mem::size_of::<usize>() - 1)? & !(mem::size_of::<usize>() - 1))
+}
@@ -0,0 +1,237 @@
+//! Timing allocations, for `ShufflingAllocator::latency`.
+//!
+//! Durations are recorded in fixed, power-of-two buckets of atomic counters, so
+//! recording never allocates or locks. Without the `latency` feature, the
+//! timers are zero-sized and timing is a no-op, so it costs nothing.
+
+/// Which operation is being timed.
+#[derive(Clone, Copy)]
+pub(crate) enum Op {
+    Alloc,
+    Dealloc,
+}
+
+/// Which part of an operation is being timed.
+#[derive(Clone, Copy)]
+pub(crate) enum Part {
+    /// The whole operation.
+    Total,
+    /// Choosing random numbers for shuffling decisions.
+    Shuffle,
+    /// Calls into the inner allocator.
+    Inner,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "latency")] {
+        use std::{
+            convert::TryFrom,
+            ops::Range,
+            sync::atomic::{AtomicU64, Ordering},
+            time::Instant,
+        };
+
+        /// The number of buckets in a histogram. The last bucket counts every
+        /// duration of 2^30 nanoseconds (about a second) or more.
+        const BUCKETS: usize = 32;
+
+        /// A histogram of durations, in power-of-two buckets of nanoseconds.
+        #[derive(Clone, Copy, Debug)]
+        pub struct LatencyHistogram {
+            buckets: [u64; BUCKETS],
+        }
+
+        impl LatencyHistogram {
+            /// The number of durations recorded in each bucket. Bucket `i` covers
+            /// the durations in [`bucket_range(i)`](#method.bucket_range).
+            pub fn buckets(&self) -> &[u64] {
+                &self.buckets
+            }
+
+            /// The range of durations, in nanoseconds, that bucket `i` covers.
+            ///
+            /// Bucket 0 is durations under a nanosecond, bucket 1 is one
+            /// nanosecond, bucket 2 is two to three nanoseconds, and so on,
+            /// doubling each time. The last bucket has no upper bound.
+            ///
+            /// # Example
+            ///
+            /// ```
+            /// use shuffling_allocator::LatencyHistogram;
+            ///
+            /// assert_eq!(LatencyHistogram::bucket_range(0), 0..1);
+            /// assert_eq!(LatencyHistogram::bucket_range(3), 4..8);
+            /// ```
+            pub fn bucket_range(i: usize) -> Range<u64> {
+                assert!(i < BUCKETS);
+                let start = if i == 0 { 0 } else { 1 << (i - 1) };
+                let end = if i == BUCKETS - 1 { u64::MAX } else { 1 << i };
+                start..end
+            }
+
+            /// The total number of durations recorded.
+            pub fn count(&self) -> u64 {
+                self.buckets.iter().sum()
+            }
+
+            /// An upper bound on the `q`th quantile of the recorded durations,
+            /// in nanoseconds, or `None` if nothing has been recorded.
+            ///
+            /// For example, `quantile(0.99)` bounds the 99th percentile. The
+            /// bound is the end of the bucket that the quantile falls in, so it
+            /// is within a factor of two of the actual duration.
+            pub fn quantile(&self, q: f64) -> Option<u64> {
+                let count = self.count();
+                if count == 0 {
+                    return None;
+                }
+                let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+                let mut seen = 0;
+                let i = self.buckets.iter().position(|&n| {
+                    seen += n;
+                    seen >= rank
+                })?;
+                Some(Self::bucket_range(i).end)
+            }
+
+            fn bucket(nanos: u64) -> usize {
+                ((u64::BITS - nanos.leading_zeros()) as usize).min(BUCKETS - 1)
+            }
+        }
+
+        /// Histograms of the time spent in one kind of operation.
+        #[derive(Clone, Copy, Debug)]
+        pub struct OpLatency {
+            /// The time spent in each call, from start to finish.
+            pub total: LatencyHistogram,
+            /// The time spent choosing each random number for shuffling
+            /// decisions, including any locking that the generator does.
+            pub shuffle: LatencyHistogram,
+            /// The time spent in each call into the inner allocator.
+            pub inner: LatencyHistogram,
+        }
+
+        /// A snapshot of a `ShufflingAllocator`'s latency histograms.
+        ///
+        /// Created by
+        /// [`ShufflingAllocator::latency`](./struct.ShufflingAllocator.html#method.latency).
+        #[derive(Clone, Copy, Debug)]
+        pub struct Latency {
+            /// Histograms for `alloc` and `alloc_zeroed`.
+            pub alloc: OpLatency,
+            /// Histograms for `dealloc`.
+            pub dealloc: OpLatency,
+        }
+
+        struct Histogram([AtomicU64; BUCKETS]);
+
+        impl Histogram {
+            fn snapshot(&self) -> LatencyHistogram {
+                LatencyHistogram {
+                    buckets: std::array::from_fn(|i| self.0[i].load(Ordering::Relaxed)),
+                }
+            }
+        }
+
+        struct OpTimers {
+            total: Histogram,
+            shuffle: Histogram,
+            inner: Histogram,
+        }
+
+        impl OpTimers {
+            fn snapshot(&self) -> OpLatency {
+                OpLatency {
+                    total: self.total.snapshot(),
+                    shuffle: self.shuffle.snapshot(),
+                    inner: self.inner.snapshot(),
+                }
+            }
+        }
+
+        /// The histograms for all of an allocator's operations.
+        pub(crate) struct Timers {
+            alloc: OpTimers,
+            dealloc: OpTimers,
+        }
+
+        impl Timers {
+            pub fn new() -> Self {
+                let op = || OpTimers {
+                    total: Histogram([const { AtomicU64::new(0) }; BUCKETS]),
+                    shuffle: Histogram([const { AtomicU64::new(0) }; BUCKETS]),
+                    inner: Histogram([const { AtomicU64::new(0) }; BUCKETS]),
+                };
+                Timers {
+                    alloc: op(),
+                    dealloc: op(),
+                }
+            }
+
+            pub fn op(&self, op: Op) -> TimerRef {
+                TimerRef(self, op)
+            }
+
+            pub fn snapshot(&self) -> Latency {
+                Latency {
+                    alloc: self.alloc.snapshot(),
+                    dealloc: self.dealloc.snapshot(),
+                }
+            }
+        }
+
+        /// A handle for timing parts of one kind of operation.
+        ///
+        /// The histograms live in the allocator's state, which outlives every
+        /// operation, so this doesn't need a lifetime.
+        #[derive(Clone, Copy)]
+        pub(crate) struct TimerRef(*const Timers, Op);
+
+        impl TimerRef {
+            /// Run `f`, recording how long it took as the given part of this
+            /// handle's operation.
+            #[inline]
+            pub fn time<T>(self, part: Part, f: impl FnOnce() -> T) -> T {
+                let start = Instant::now();
+                let x = f();
+                let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+
+                let timers = unsafe { &*self.0 };
+                let op = match self.1 {
+                    Op::Alloc => &timers.alloc,
+                    Op::Dealloc => &timers.dealloc,
+                };
+                let histogram = match part {
+                    Part::Total => &op.total,
+                    Part::Shuffle => &op.shuffle,
+                    Part::Inner => &op.inner,
+                };
+                histogram.0[LatencyHistogram::bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+                x
+            }
+        }
+    } else {
+        pub(crate) struct Timers;
+
+        impl Timers {
+            pub fn new() -> Self {
+                Timers
+            }
+
+            #[inline]
+            pub fn op(&self, _op: Op) -> TimerRef {
+                TimerRef
+            }
+        }
+
+        #[derive(Clone, Copy)]
+        pub(crate) struct TimerRef;
+
+        impl TimerRef {
+            #[inline]
+            pub fn time<T>(self, _part: Part, f: impl FnOnce() -> T) -> T {
+                f()
+            }
+        }
+    }
+}
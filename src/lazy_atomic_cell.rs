@@ -1,18 +1,15 @@
 use std::{
     alloc::{handle_alloc_error, GlobalAlloc, Layout},
-    ptr,
+    mem, ptr,
     sync::atomic::{AtomicPtr, Ordering},
 };
 
-#[doc(hidden)]
-pub struct LazyAtomicCell<A, T>
+pub(crate) struct LazyAtomicCell<A, T>
 where
     A: 'static + GlobalAlloc,
 {
-    #[doc(hidden)]
-    pub ptr: AtomicPtr<T>,
-    #[doc(hidden)]
-    pub allocator: &'static A,
+    ptr: AtomicPtr<T>,
+    allocator: &'static A,
 }
 
 impl<A, T> Drop for LazyAtomicCell<A, T>
@@ -36,13 +33,24 @@ where
     A: 'static + GlobalAlloc,
 {
     /// Create a new `LazyAtomicCell`.
-    pub fn new(allocator: &'static A) -> Self {
+    pub const fn new(allocator: &'static A) -> Self {
         LazyAtomicCell {
             ptr: AtomicPtr::new(ptr::null_mut()),
             allocator,
         }
     }
 
+    /// The allocator that this cell's value is allocated in.
+    pub fn allocator(&self) -> &'static A {
+        self.allocator
+    }
+
+    /// Take the value out of the cell, if it has been created, leaving the
+    /// cell empty. The caller is responsible for dropping and deallocating it.
+    pub fn take(&mut self) -> *mut T {
+        mem::replace(self.ptr.get_mut(), ptr::null_mut())
+    }
+
     /// Get the value if it already exists, or create it by calling `init`.
     pub fn get_or_create(&self, init: impl FnOnce() -> T) -> &T {
         let ptr = self.ptr.load(Ordering::SeqCst);
@@ -65,20 +73,22 @@ where
         // Attempt to initialize `self.ptr` with our newly allocated and
         // initialized `T`. We are racing against other threads to be the first
         // to initialize `self.ptr`.
-        let existing_ptr = self
-            .ptr
-            .compare_and_swap(ptr::null_mut(), new_ptr, Ordering::SeqCst);
-        if existing_ptr.is_null() {
+        match self.ptr.compare_exchange(
+            ptr::null_mut(),
+            new_ptr,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
             // We won the race!
-            unsafe { &*new_ptr }
-        } else {
+            Ok(_) => unsafe { &*new_ptr },
+
             // We lost the race, so we have to remember to drop and deallocate
             // our now-unnecessary `State`.
-            unsafe {
+            Err(existing_ptr) => unsafe {
                 ptr::drop_in_place(new_ptr);
                 self.allocator.dealloc(new_ptr.cast(), layout);
                 &*existing_ptr
-            }
+            },
         }
     }
 }
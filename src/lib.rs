@@ -32,8 +32,39 @@
 //! pointer. The larger the array in the shuffling layer, the closer to truly
 //! randomized heap allocations we get, but also the greater the
 //! overhead. Curtsinger and Berger found that arrays of size 256 gave good
-//! randomization for acceptable overhead, and that is also the array size that
-//! this crate uses.
+//! randomization for acceptable overhead, and that is also the default array
+//! size that this crate uses. A different size may be chosen with
+//! `ShufflingAllocator`'s `N` const parameter, for example
+//! `ShufflingAllocator<System, 1024>` for stronger randomization or
+//! `ShufflingAllocator<System, 16>` for lower overhead. Likewise, the size
+//! classes can be customized with a `SizeClassTable`, for example to match the
+//! bins of the wrapped allocator. Other ways of shuffling through the arrays, such
+//! as only shuffling frees or handing out objects in shuffled batches, can be
+//! chosen with `ShufflingAllocator`'s `S` parameter; see the `ShuffleStrategy`
+//! trait.
+//!
+//! Allocations aligned to more than a word, such as `u128`s and SIMD vectors, are
+//! shuffled too, in separate arrays for each alignment up to 64 bytes (one cache
+//! line). Allocations with even greater alignment are passed straight through to
+//! the wrapped allocator.
+//!
+//! Allocations larger than the largest size class are passed straight through to
+//! the wrapped allocator by default as well. Optionally, their placement can be
+//! randomized at page granularity instead: each one is over-allocated by a
+//! configurable number of pages, and placed a random number of pages into the
+//! resulting block. See `ShufflingAllocatorBuilder::large_object_pages`.
+//!
+//! Random indices come from a lock-free generator whose state is a single atomic
+//! counter, so threads allocating concurrently never wait on each other to make
+//! their shuffling decisions. Other generators can be chosen with
+//! `ShufflingAllocator`'s `R` parameter; see the `ShuffleRng` trait. Each size
+//! class draws from its own stream of random numbers, derived from the allocator's
+//! seed, so that the layout of one size class doesn't depend on how many
+//! allocations were made in the others. All threads share the same shuffling arrays by
+//! default; to keep objects on the thread that allocated them, as thread-caching
+//! allocators do, see `ShufflingAllocatorBuilder::thread_local_arrays`, or for
+//! arrays per CPU rather than per thread,
+//! `ShufflingAllocatorBuilder::per_cpu_arrays`.
 //!
 //! # Example
 //!
@@ -45,89 +76,197 @@
 //! use std::alloc::System;
 //!
 //! static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> =
-//!     shuffling_allocator::wrap!(&System);
+//!     ShufflingAllocator::new(&System);
+//! ```
+//!
+//! Use a builder to configure the shuffling, for example to make it
+//! deterministic with a fixed seed:
+//!
+//! ```
+//! use shuffling_allocator::ShufflingAllocator;
+//! use std::alloc::System;
+//!
+//! static SEEDED_SYSTEM_ALLOC: ShufflingAllocator<System> =
+//!     ShufflingAllocator::builder(&System).seed(42).build();
 //! ```
+//!
+//! # Statistics
+//!
+//! With the `stats` cargo feature enabled, `ShufflingAllocator::stats` reports
+//! what the allocator has been doing: how many allocations and frees each size
+//! class has seen, how many allocations weren't shuffled and why, how many bytes
+//! are sitting in the shuffling arrays, how many bytes are live, and how long
+//! threads have waited on the random number generator's lock, for generators that
+//! have one. Calling `shuffling_allocator::phase("warmup")`,
+//! `phase("measure")`, and so on at the start of each phase of a program breaks
+//! these statistics down by phase, so that a benchmark's setup doesn't hide what
+//! happened while it was measured. Likewise, with the `latency` feature enabled,
+//! `ShufflingAllocator::latency` reports histograms of how long allocations and
+//! frees take, split into the time spent making shuffling decisions and the time
+//! spent in the wrapped allocator, to bound the overhead that shuffling adds to a
+//! benchmark. Without these features, none of this is measured, and it costs
+//! nothing.
+//!
+//! Statistics can be written as JSON, CSV, or Prometheus text with `Stats::write`,
+//! or written to a file when the process exits with
+//! `ShufflingAllocator::dump_stats_at_exit`.
+//!
+//! # Environment Variables
+//!
+//! The following environment variables are read when a `ShufflingAllocator` is
+//! first used, so that one build of a program can be run under different
+//! configurations:
+//!
+//! * `SHUFFLING_ALLOCATOR_SEED`: a decimal or `0x`-prefixed hexadecimal seed for
+//!   the shuffling decisions. This takes precedence over a seed given to
+//!   `ShufflingAllocatorBuilder::seed`.
+//!
+//! * `SHUFFLING_ALLOCATOR_DISABLE`: if set to anything other than an empty
+//!   string, `0`, or `false`, disables shuffling and passes every allocation
+//!   straight through to the wrapped allocator.
+//!
+//! * `SHUFFLING_ALLOCATOR_ARRAY_SIZE`: the number of entries of each shuffling
+//!   array to use, from 1 up to the allocator's array size `N`. Smaller arrays
+//!   have less overhead but randomize less. This takes precedence over
+//!   `ShufflingAllocatorBuilder::array_size`.
+//!
+//! * `SHUFFLING_ALLOCATOR_LARGE_PAGES`: the number of pages of padding used to
+//!   randomize the placement of large allocations, or 0 to pass them straight
+//!   through. This takes precedence over
+//!   `ShufflingAllocatorBuilder::large_object_pages`.
 
 #![deny(missing_docs)]
 
+mod builder;
+mod cpu;
+mod env;
+#[cfg(feature = "stats")]
+mod export;
+mod large;
+mod latency;
 mod lazy_atomic_cell;
+mod phase;
+mod policy;
+mod rng;
+mod scoped;
+mod size_classes;
+mod stats;
+mod strategy;
+mod thread_arrays;
+mod thread_identity;
 
-cfg_if::cfg_if! {
-    if #[cfg(unix)] {
-        mod pthread_mutex;
-        use pthread_mutex::PthreadMutex as Mutex;
-    } else if #[cfg(windows)] {
-        mod windows_mutex;
-        use windows_mutex::WindowsMutex as Mutex;
-    } else {
-        compile_error!("no mutex implementation for this platform");
-    }
-}
-
-// This is only public because we can't use type parameters in `const fn` yet,
-// so we can't implement `const fn ShufflingAllocator::new`. Don't use this!
-#[doc(hidden)]
-pub use lazy_atomic_cell::LazyAtomicCell;
+pub use builder::ShufflingAllocatorBuilder;
+#[cfg(feature = "stats")]
+pub use export::StatsFormat;
+#[cfg(feature = "latency")]
+pub use latency::{Latency, LatencyHistogram, OpLatency};
+pub use phase::phase;
+pub use policy::ClassPolicy;
+pub use rng::{Locked, RngSnapshot, ShuffleRng, SmallRng, SplitMix64, WyRand, Xorshift64};
+pub use scoped::{unshuffled, with_seed};
+pub use size_classes::SizeClassTable;
+#[cfg(feature = "stats")]
+pub use stats::{ClassStats, LockContention, PhaseStats, Stats};
+pub use strategy::{
+    BatchRefill, DelayedReuse, ShuffleStrategy, Slots, SwapOnAlloc, SwapOnAllocAndFree, SwapOnFree,
+};
 
+use builder::Config;
+use env::EnvConfig;
+use latency::{Op, Part, TimerRef, Timers};
+use lazy_atomic_cell::LazyAtomicCell;
 use mem::MaybeUninit;
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::{rngs::OsRng, Rng};
+use size_classes::SizeClassInfo;
+use stats::{Bypass, ClassRef, Counters};
 use std::{
     alloc::{handle_alloc_error, GlobalAlloc, Layout},
+    cell::Cell,
+    marker::PhantomData,
     mem, ptr,
-    sync::atomic::{AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
 };
+use thread_identity::ThreadNames;
+
+/// Allocations with an alignment greater than this are not shuffled. It is one
+/// cache line on most targets.
+const MAX_SHUFFLED_ALIGN: usize = 64;
 
-const SHUFFLING_ARRAY_SIZE: usize = 256;
+/// The number of alignments that get their own shuffling arrays: every power
+/// of two from the word's alignment up to `MAX_SHUFFLED_ALIGN`.
+const NUM_ALIGN_CLASSES: usize =
+    (MAX_SHUFFLED_ALIGN.trailing_zeros() - mem::align_of::<usize>().trailing_zeros() + 1) as usize;
 
-struct ShufflingArray<A>
+/// The total number of shuffling arrays, across all alignments.
+const NUM_SIZE_CLASSES: usize = NUM_ALIGN_CLASSES * SizeClassTable::MAX_CLASSES;
+
+/// The number of CPUs that get their own size classes with per-CPU arrays. CPUs
+/// beyond this share with lower-numbered CPUs.
+const MAX_CPUS: usize = 256;
+
+/// Get the index of the shuffling arrays for allocations aligned to `align`.
+#[inline]
+fn align_class(align: usize) -> usize {
+    debug_assert!(align.is_power_of_two() && align <= MAX_SHUFFLED_ALIGN);
+    let align = align.max(mem::align_of::<usize>());
+    (align.trailing_zeros() - mem::align_of::<usize>().trailing_zeros()) as usize
+}
+
+struct ShufflingArray<A, const N: usize>
 where
     A: 'static + GlobalAlloc,
 {
-    elems: [AtomicPtr<u8>; SHUFFLING_ARRAY_SIZE],
+    elems: [AtomicPtr<u8>; N],
+    /// The number of entries in use, which is the size of the randomization
+    /// window.
+    len: usize,
+    /// The counter that `Slots::cursor` exposes to the shuffle strategy.
+    cursor: AtomicUsize,
     size_class: usize,
+    align: usize,
+    stats: ClassRef,
     allocator: &'static A,
 }
 
-impl<A> Drop for ShufflingArray<A>
+impl<A, const N: usize> Drop for ShufflingArray<A, N>
 where
     A: 'static + GlobalAlloc,
 {
     fn drop(&mut self) {
-        let layout =
-            unsafe { Layout::from_size_align_unchecked(self.size_class, mem::align_of::<usize>()) };
+        let layout = self.elem_layout();
         for el in &self.elems {
             let p = el.swap(ptr::null_mut(), Ordering::SeqCst);
             if !p.is_null() {
                 unsafe {
                     self.allocator.dealloc(p, layout);
                 }
+                self.stats.parked(-1);
             }
         }
     }
 }
 
-impl<A> ShufflingArray<A>
+impl<A, const N: usize> ShufflingArray<A, N>
 where
     A: 'static + GlobalAlloc,
 {
-    fn new(size_class: usize, allocator: &'static A) -> Self {
-        let elems = unsafe {
-            let mut elems = MaybeUninit::<[AtomicPtr<u8>; 256]>::uninit();
-            let elems_ptr: *mut [AtomicPtr<u8>; 256] = elems.as_mut_ptr();
-            let elems_ptr: *mut AtomicPtr<u8> = elems_ptr.cast();
-            let layout = Layout::from_size_align_unchecked(size_class, mem::align_of::<usize>());
-            for i in 0..256 {
-                let p = allocator.alloc(layout);
-                if p.is_null() {
-                    handle_alloc_error(layout);
-                }
-                ptr::write(elems_ptr.offset(i), AtomicPtr::new(p));
-            }
-            elems.assume_init()
-        };
+    /// Create a new, empty shuffling array for the given size class and
+    /// alignment, whose first `len` entries are used.
+    fn new(
+        size_class: usize,
+        align: usize,
+        len: usize,
+        stats: ClassRef,
+        allocator: &'static A,
+    ) -> Self {
+        debug_assert!(len <= N);
         ShufflingArray {
-            elems,
+            elems: [const { AtomicPtr::new(ptr::null_mut()) }; N],
+            len,
+            cursor: AtomicUsize::new(0),
             size_class,
+            align,
+            stats,
             allocator,
         }
     }
@@ -136,222 +275,245 @@ where
     /// this shuffing array.
     fn elem_layout(&self) -> Layout {
         unsafe {
-            debug_assert!(
-                Layout::from_size_align(self.size_class, mem::align_of::<usize>()).is_ok()
-            );
-            Layout::from_size_align_unchecked(self.size_class, mem::align_of::<usize>())
+            debug_assert!(Layout::from_size_align(self.size_class, self.align).is_ok());
+            Layout::from_size_align_unchecked(self.size_class, self.align)
         }
     }
+
+    /// Get the view of this array's window that shuffle strategies work on,
+    /// with its random number stream `rng`, timing it with `timer`.
+    #[inline]
+    fn slots<'a, R: ShuffleRng>(&'a self, rng: &'a R, timer: TimerRef) -> Slots<'a, A, R> {
+        Slots::new(
+            &self.elems[..self.len],
+            &self.cursor,
+            self.elem_layout(),
+            self.stats,
+            timer,
+            self.allocator,
+            rng,
+        )
+    }
 }
 
-struct SizeClasses<A>([LazyAtomicCell<A, ShufflingArray<A>>; NUM_SIZE_CLASSES])
+/// The shuffling arrays for each alignment and size class, indexed by
+/// `align_class * SizeClassTable::MAX_CLASSES + size_class_index`.
+struct SizeClasses<A, const N: usize>([LazyAtomicCell<A, ShufflingArray<A, N>>; NUM_SIZE_CLASSES])
 where
     A: 'static + GlobalAlloc;
 
-struct SizeClassInfo {
-    index: usize,
-    size_class: usize,
-}
-
-#[rustfmt::skip]
-#[inline]
-fn size_class_info(size: usize) -> Option<SizeClassInfo> {
-    let mut size_class = mem::size_of::<usize>();
-    let mut stride = mem::size_of::<usize>();
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 0, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 1, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 2, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 3, size_class });
-    }
-    size_class += stride;
-
-    stride = stride * 2;
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 4, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 5, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 6, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 7, size_class });
-    }
-    size_class += stride;
-
-    stride = stride * 2;
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 8, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 9, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 10, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 11, size_class });
-    }
-    size_class += stride;
-
-    stride = stride * 2;
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 12, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 13, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 14, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 15, size_class });
-    }
-    size_class += stride;
-
-    stride = stride * 2;
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 16, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 17, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 18, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 19, size_class });
+impl<A, const N: usize> SizeClasses<A, N>
+where
+    A: 'static + GlobalAlloc,
+{
+    /// Create a set of size classes whose arrays are all created lazily.
+    fn new(allocator: &'static A) -> Self {
+        let mut classes =
+            MaybeUninit::<[LazyAtomicCell<A, ShufflingArray<A, N>>; NUM_SIZE_CLASSES]>::uninit();
+        unsafe {
+            for i in 0..NUM_SIZE_CLASSES {
+                ptr::write(
+                    classes
+                        .as_mut_ptr()
+                        .cast::<LazyAtomicCell<A, ShufflingArray<A, N>>>()
+                        .add(i),
+                    LazyAtomicCell::new(allocator),
+                );
+            }
+            SizeClasses(classes.assume_init())
+        }
     }
-    size_class += stride;
+}
 
-    stride = stride * 2;
+/// A thread's own size classes, and the random number streams that it shuffles
+/// them with.
+struct ThreadSizeClasses<A, const N: usize, R>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+{
+    classes: SizeClasses<A, N>,
+    streams: [R; NUM_SIZE_CLASSES],
+    /// The allocator's state, which these size classes hold a reference to, so
+    /// that it outlives their arrays even if the allocator is dropped first.
+    state: *mut State<A, N, R>,
+    /// The thread's identity, which its streams are derived from.
+    identity: u64,
+    /// The allocator's `State::epoch` when the streams were last derived.
+    epoch: Cell<u64>,
+}
 
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 20, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 21, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 22, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 23, size_class });
+impl<A, const N: usize, R> ThreadSizeClasses<A, N, R>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+{
+    /// Allocate a set of size classes for a thread's own use, with streams
+    /// derived from `seed` and the thread's `identity`, taking a reference to
+    /// `state`.
+    fn create(
+        allocator: &'static A,
+        state: &State<A, N, R>,
+        seed: u64,
+        identity: u64,
+        epoch: u64,
+    ) -> (*mut (), unsafe fn(*mut ())) {
+        let layout = Layout::new::<Self>();
+        unsafe {
+            let classes = allocator.alloc(layout).cast::<Self>();
+            if classes.is_null() {
+                handle_alloc_error(layout);
+            }
+            ptr::write(
+                classes,
+                ThreadSizeClasses {
+                    classes: SizeClasses::new(allocator),
+                    streams: rng::streams(rng::derive_seed(seed, identity)),
+                    state: state as *const State<A, N, R> as *mut State<A, N, R>,
+                    identity,
+                    epoch: Cell::new(epoch),
+                },
+            );
+            state.refs.fetch_add(1, Ordering::Relaxed);
+            (classes.cast(), Self::free)
+        }
     }
-    size_class += stride;
 
-    stride = stride * 2;
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 24, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 25, size_class });
+    /// Free a thread's size classes, returning all of their arrays' entries to
+    /// the inner allocator, and then release their reference to the state.
+    unsafe fn free(classes: *mut ()) {
+        let classes = classes.cast::<Self>();
+        let allocator = (*classes).classes.0[0].allocator();
+        let state = (*classes).state;
+        ptr::drop_in_place(classes);
+        allocator.dealloc(classes.cast(), Layout::new::<Self>());
+        State::release(state, allocator);
     }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 26, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 27, size_class });
-    }
-    size_class += stride;
-
-    stride = stride * 2;
-
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 28, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 29, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 30, size_class });
-    }
-    size_class += stride;
-    if size <= size_class {
-        return Some(SizeClassInfo { index: 31, size_class });
-    }
-
-    None
 }
 
-const NUM_SIZE_CLASSES: usize = 32;
-
 /// A shuffling allocator.
 ///
 /// Wraps an existing allocator and shuffles the order of heap allocations
 /// yielded.
 ///
+/// The `N` parameter is the number of entries in each size class's shuffling
+/// array. Larger arrays randomize heap layout more thoroughly, at the cost of
+/// more overhead. It defaults to 256.
+///
+/// The `R` parameter is the random number generator used to make shuffling
+/// decisions. It defaults to [`SplitMix64`]; see [`ShuffleRng`] for the other
+/// choices.
+///
+/// The `S` parameter is the algorithm used to shuffle allocations through the
+/// shuffling arrays. It defaults to [`SwapOnAllocAndFree`]; see
+/// [`ShuffleStrategy`] for the other choices.
+///
 /// See [the crate-level documentation](./index.html) for more details.
 ///
 /// # Example
 ///
 /// ```
-/// use shuffling_allocator::ShufflingAllocator;
+/// use shuffling_allocator::{BatchRefill, ShufflingAllocator, SplitMix64, WyRand};
 /// use std::alloc::System;
 ///
-/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> = shuffling_allocator::wrap!(&System);
+/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+///
+/// // Use smaller, cheaper shuffling arrays.
+/// static LESS_SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System, 16> =
+///     ShufflingAllocator::new(&System);
+///
+/// // Use a cheaper random number generator.
+/// static WYRAND_SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System, 256, WyRand> =
+///     ShufflingAllocator::new(&System);
+///
+/// // Hand out objects in shuffled batches.
+/// static BATCH_SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System, 256, SplitMix64, BatchRefill> =
+///     ShufflingAllocator::new(&System);
 /// ```
-pub struct ShufflingAllocator<A>
+pub struct ShufflingAllocator<A, const N: usize = 256, R = SplitMix64, S = SwapOnAllocAndFree>
 where
     A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
 {
-    // XXX: You shouldn't touch these fields! They need to be `pub` so that
-    // `wrap!` works, but as soon as `const fn`s can have type parameters, these
-    // will be private.
-    #[doc(hidden)]
-    pub inner: &'static A,
-    #[doc(hidden)]
-    pub state: LazyAtomicCell<A, State<A>>,
+    inner: &'static A,
+    config: Config,
+    /// The runtime switch set by `set_enabled`.
+    shuffling: AtomicBool,
+    state: LazyAtomicCell<A, State<A, N, R>>,
+    strategy: PhantomData<fn() -> S>,
 }
 
-#[doc(hidden)]
-pub struct State<A>
+struct State<A, const N: usize, R>
 where
     A: 'static + GlobalAlloc,
+    R: ShuffleRng,
 {
-    rng: Mutex<A, StdRng>,
-    size_classes: LazyAtomicCell<A, SizeClasses<A>>,
+    /// A unique ID for this allocator, which threads' own arrays are kept under.
+    id: u64,
+    /// The number of references to this state: one from the allocator, until
+    /// it is dropped, and one from each thread's own size classes.
+    refs: AtomicUsize,
+    seed: AtomicU64,
+    /// Bumped whenever the streams are reseeded or restored, so that threads
+    /// know to rederive their own streams.
+    epoch: AtomicU64,
+    enabled: bool,
+    array_size: usize,
+    large_object_pages: usize,
+    /// The policy for each size class in the table.
+    policies: [ClassPolicy; SizeClassTable::MAX_CLASSES],
+    /// One random number stream for each shuffling array, and a final one for
+    /// placing large objects.
+    streams: [R; NUM_SIZE_CLASSES + 1],
+    thread_names: ThreadNames,
+    stats: Counters,
+    timers: Timers,
+    size_classes: LazyAtomicCell<A, SizeClasses<A, N>>,
+    cpu_size_classes: LazyAtomicCell<A, [LazyAtomicCell<A, SizeClasses<A, N>>; MAX_CPUS]>,
+}
+
+impl<A, const N: usize, R> State<A, N, R>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+{
+    /// Release a reference to `state`, dropping and deallocating it from
+    /// `allocator` if that was the last one.
+    unsafe fn release(state: *mut Self, allocator: &'static A) {
+        if (*state).refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            ptr::drop_in_place(state);
+            allocator.dealloc(state.cast(), Layout::new::<Self>());
+        }
+    }
+}
+
+impl<A, const N: usize, R, S> Drop for ShufflingAllocator<A, N, R, S>
+where
+    A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
+{
+    fn drop(&mut self) {
+        // Threads' own size classes may still refer to the state, in which case
+        // the last of them to be freed frees it.
+        let state = self.state.take();
+        if !state.is_null() {
+            unsafe { State::release(state, self.inner) }
+        }
+    }
 }
 
 /// Wrap shuffling around an existing global allocator.
 ///
+/// This is shorthand for
+/// [`ShufflingAllocator::new`](./struct.ShufflingAllocator.html#method.new),
+/// or, when the optional `seed = <u64>` argument is given, for building a
+/// `ShufflingAllocator` with that seed. Without a seed, one is chosen from the
+/// operating system's entropy source when the allocator is first used. Either
+/// way, the seed that was actually used is available from
+/// [`ShufflingAllocator::seed`](./struct.ShufflingAllocator.html#method.seed).
+///
 /// # Example
 ///
 /// ```
@@ -359,103 +521,635 @@ where
 /// use std::alloc::System;
 ///
 /// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> = shuffling_allocator::wrap!(&System);
+///
+/// static SEEDED_SYSTEM_ALLOC: ShufflingAllocator<System> =
+///     shuffling_allocator::wrap!(&System, seed = 0x1234_5678);
 /// ```
 #[macro_export]
 macro_rules! wrap {
     ($inner:expr) => {
-        $crate::ShufflingAllocator {
-            inner: $inner,
-            state: $crate::LazyAtomicCell {
-                ptr: ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut()),
-                allocator: $inner,
-            },
-        }
+        $crate::ShufflingAllocator::new($inner)
+    };
+    ($inner:expr, seed = $seed:expr) => {
+        $crate::ShufflingAllocator::builder($inner)
+            .seed($seed)
+            .build()
     };
 }
 
-impl<A> ShufflingAllocator<A>
+impl<A, const N: usize, R, S> ShufflingAllocator<A, N, R, S>
 where
     A: 'static + GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
 {
-    // XXX: this is disabled until we can have `const fn`s with type parameters.
-    //
-    // pub const fn new(inner: &'static A) -> Self {
-    //     ShufflingAllocator {
-    //         inner,
-    //         state: LazyAtomicCell::new(inner),
-    //     }
-    // }
+    const ASSERT_ARRAY_SIZE_IS_NOT_ZERO: () = assert!(N > 0, "shuffling arrays cannot be empty");
+
+    /// Wrap shuffling around `inner`, using the default configuration.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    /// ```
+    pub const fn new(inner: &'static A) -> Self {
+        Self::builder(inner).build()
+    }
+
+    /// Create a builder for configuring a `ShufflingAllocator` that wraps
+    /// `inner`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> =
+    ///     ShufflingAllocator::builder(&System).seed(42).array_size(64).build();
+    /// ```
+    pub const fn builder(inner: &'static A) -> ShufflingAllocatorBuilder<A, N, R, S> {
+        ShufflingAllocatorBuilder::new(inner)
+    }
+
+    /// Get the seed used for this allocator's shuffling decisions.
+    ///
+    /// This is the seed from the `SHUFFLING_ALLOCATOR_SEED` environment
+    /// variable if it is set, otherwise the seed given to the
+    /// [builder](./struct.ShufflingAllocatorBuilder.html#method.seed) or, if
+    /// none was given, the seed that was chosen from the operating system's
+    /// entropy source, unless the allocator has since been
+    /// [reseeded](#method.reseed). Passing this value back in as the seed of a new run
+    /// replays the same sequence of random choices; for the heap layout to be
+    /// reproduced as well, the program must also make the same sequence of
+    /// allocations in each size class.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// static SEEDED: ShufflingAllocator<System> =
+    ///     ShufflingAllocator::builder(&System).seed(42).build();
+    ///
+    /// assert_eq!(SEEDED.seed(), 42);
+    /// ```
+    pub fn seed(&self) -> u64 {
+        self.state().seed.load(Ordering::SeqCst)
+    }
+
+    /// Start making shuffling decisions from a new seed, as if the allocator
+    /// had been created with it.
+    ///
+    /// This resets the random number streams, but not the contents of the
+    /// shuffling arrays, nor the inner allocator's heap. It is meant to be
+    /// called between benchmark iterations, to try many layouts in one
+    /// process; for the results to be reproducible, no other threads should be
+    /// allocating at the time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// for seed in 0..10 {
+    ///     SHUFFLED.reseed(seed);
+    ///     // Run a benchmark iteration...
+    ///     assert_eq!(SHUFFLED.seed(), seed);
+    /// }
+    /// ```
+    pub fn reseed(&self, seed: u64) {
+        let state = self.state();
+        state.seed.store(seed, Ordering::SeqCst);
+        rng::reseed_streams(&state.streams, seed);
+        state.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Turn shuffling on or off while the program is running.
+    ///
+    /// While shuffling is off, allocations are passed straight through to the
+    /// inner allocator, except that allocations are still rounded up to their
+    /// size class, and large objects still get the header that records where
+    /// they were placed. That way, objects allocated while shuffling was on can
+    /// be freed while it is off, and vice versa. This lets a benchmark run its
+    /// setup and warm-up unshuffled, and only shuffle the section that is
+    /// being measured. To turn shuffling off for just one thread, see
+    /// [`unshuffled`](./fn.unshuffled.html).
+    ///
+    /// This has no effect if shuffling was disabled when the allocator was
+    /// [built](./struct.ShufflingAllocatorBuilder.html#method.enabled) or by
+    /// the `SHUFFLING_ALLOCATOR_DISABLE` environment variable, in which case
+    /// the allocator is a pure pass-through and stays that way.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// #[global_allocator]
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// SHUFFLED.set_enabled(false);
+    /// let input: Vec<u64> = (0..1000).collect();
+    ///
+    /// SHUFFLED.set_enabled(true);
+    /// let output: Vec<Box<u64>> = input.iter().copied().map(Box::new).collect();
+    /// ```
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shuffling.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Whether this allocator is currently shuffling allocations.
+    ///
+    /// This is false if shuffling was disabled when the allocator was built,
+    /// by the environment, or by [`set_enabled`](#method.set_enabled). It
+    /// doesn't take [`unshuffled`](./fn.unshuffled.html) scopes into account.
+    pub fn is_enabled(&self) -> bool {
+        self.state().enabled && self.shuffling.load(Ordering::SeqCst)
+    }
+
+    /// Whether to shuffle allocations on the current thread right now.
+    #[inline]
+    fn is_shuffling(&self) -> bool {
+        self.shuffling.load(Ordering::Relaxed) && !scoped::is_unshuffled()
+    }
+
+    /// Save the state of this allocator's random number generators.
+    ///
+    /// Passing the snapshot to
+    /// [`restore_rng`](#method.restore_rng) later makes the same shuffling
+    /// decisions again from that point on. This includes the streams shared
+    /// between threads, but not each thread's own streams when
+    /// [per-thread arrays](./struct.ShufflingAllocatorBuilder.html#method.thread_local_arrays)
+    /// are used; restoring a snapshot starts those over from the snapshot's
+    /// seed instead.
+    ///
+    /// Taking a snapshot doesn't allocate, so it doesn't disturb the state
+    /// that it is saving.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// let snapshot = SHUFFLED.rng_snapshot();
+    /// // Try a layout...
+    /// SHUFFLED.restore_rng(&snapshot);
+    /// // ...and make the same decisions again.
+    /// ```
+    pub fn rng_snapshot(&self) -> RngSnapshot<R> {
+        let state = self.state();
+        RngSnapshot {
+            seed: state.seed.load(Ordering::SeqCst),
+            streams: std::array::from_fn(|i| state.streams[i].save()),
+        }
+    }
+
+    /// Restore the state of this allocator's random number generators from a
+    /// snapshot taken by [`rng_snapshot`](#method.rng_snapshot).
+    pub fn restore_rng(&self, snapshot: &RngSnapshot<R>) {
+        let state = self.state();
+        state.seed.store(snapshot.seed, Ordering::SeqCst);
+        for (stream, saved) in state.streams.iter().zip(&snapshot.streams) {
+            stream.restore(saved);
+        }
+        state.epoch.fetch_add(1, Ordering::SeqCst);
+    }
 
+    /// Take a snapshot of this allocator's statistics.
+    ///
+    /// This is only available with the `stats` cargo feature. The counters are
+    /// updated independently of each other, so a snapshot taken while other
+    /// threads are allocating may be slightly inconsistent. Taking a snapshot
+    /// doesn't allocate.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// #[global_allocator]
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// let boxes: Vec<_> = (0..100).map(Box::new).collect();
+    /// let stats = SHUFFLED.stats();
+    /// assert!(stats.live_bytes >= 100 * std::mem::size_of::<i32>());
+    /// for class in stats.classes() {
+    ///     println!("{} bytes: {} allocations", class.size, class.allocs);
+    /// }
+    /// # drop(boxes);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        let state = self.state();
+        let rng_lock = state.streams.iter().map(|stream| stream.contention()).fold(
+            LockContention::default(),
+            |total, lock| LockContention {
+                contended: total.contended + lock.contended,
+                wait_nanos: total.wait_nanos + lock.wait_nanos,
+            },
+        );
+        state.stats.snapshot(self.config.size_classes, rng_lock)
+    }
+
+    /// Take a snapshot of this allocator's latency histograms.
+    ///
+    /// This is only available with the `latency` cargo feature. Comparing the
+    /// time spent in the inner allocator with the total time shows how much
+    /// overhead shuffling adds. Timing every call has some overhead of its
+    /// own, so the totals are larger than they would be without the feature.
+    /// Resizing an allocation in place isn't timed, but the allocations and
+    /// frees that moving one between size classes takes are.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::ShufflingAllocator;
+    /// use std::alloc::System;
+    ///
+    /// #[global_allocator]
+    /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    ///
+    /// let boxes: Vec<_> = (0..100).map(Box::new).collect();
+    /// let latency = SHUFFLED.latency();
+    /// println!(
+    ///     "median alloc: <{:?}ns, of which inner: <{:?}ns",
+    ///     latency.alloc.total.quantile(0.5),
+    ///     latency.alloc.inner.quantile(0.5),
+    /// );
+    /// # drop(boxes);
+    /// ```
+    #[cfg(feature = "latency")]
+    pub fn latency(&self) -> Latency {
+        self.state().timers.snapshot()
+    }
+
+    /// Get the timer for the given operation. Without the `latency` feature,
+    /// it is zero-sized, and getting it doesn't touch the state.
     #[inline]
-    fn state(&self) -> &State<A> {
-        self.state.get_or_create(|| State {
-            rng: Mutex::new(&self.inner, StdRng::from_entropy()),
-            size_classes: LazyAtomicCell::new(self.inner),
+    fn timer(&self, op: Op) -> TimerRef {
+        #[cfg(feature = "latency")]
+        {
+            self.state().timers.op(op)
+        }
+        #[cfg(not(feature = "latency"))]
+        {
+            Timers.op(op)
+        }
+    }
+
+    /// Get the counters that statistics are collected in. Without the `stats`
+    /// feature, they are zero-sized, and getting them doesn't touch the state.
+    #[inline]
+    fn counters(&self) -> &Counters {
+        #[cfg(feature = "stats")]
+        {
+            &self.state().stats
+        }
+        #[cfg(not(feature = "stats"))]
+        {
+            &Counters
+        }
+    }
+
+    /// Count an allocation of `size` bytes that took `route` and returned
+    /// `ptr`.
+    #[inline]
+    fn record_alloc(&self, route: &Route<'_, A, N, R>, size: usize, ptr: *mut u8) {
+        let counters = self.counters();
+        match *route {
+            Route::Inner(bypass) => counters.bypassed(bypass),
+            Route::Unshuffled(_) => counters.bypassed(Bypass::Unshuffled),
+            Route::Shuffled(array, _) if !ptr.is_null() => array.stats.alloc(),
+            _ => {}
+        }
+        if !ptr.is_null() {
+            counters.allocated(size);
+        }
+    }
+
+    #[inline]
+    fn state(&self) -> &State<A, N, R> {
+        let () = Self::ASSERT_ARRAY_SIZE_IS_NOT_ZERO;
+
+        self.state.get_or_create(|| {
+            let env = EnvConfig::read();
+            let seed = env.seed.or(self.config.seed).unwrap_or_else(|| OsRng.gen());
+            let array_size = env
+                .array_size
+                .map_or(self.config.array_size, |n| n.clamp(1, N));
+            State {
+                id: thread_arrays::new_owner(),
+                refs: AtomicUsize::new(1),
+                seed: AtomicU64::new(seed),
+                epoch: AtomicU64::new(0),
+                enabled: self.config.enabled && !env.disable,
+                array_size,
+                large_object_pages: env
+                    .large_object_pages
+                    .unwrap_or(self.config.large_object_pages),
+                policies: std::array::from_fn(|i| {
+                    let sizes = self.config.size_classes.sizes();
+                    sizes.get(i).map_or(ClassPolicy::Shuffle, |&size| {
+                        self.config.policies.policy(size)
+                    })
+                }),
+                streams: rng::streams(seed),
+                thread_names: ThreadNames::new(),
+                stats: Counters::new(),
+                timers: Timers::new(),
+                size_classes: LazyAtomicCell::new(self.inner),
+                cpu_size_classes: LazyAtomicCell::new(self.inner),
+            }
         })
     }
 
+    /// Get a random number in `0..n` from `rng`, unless the current thread is
+    /// inside a `with_seed` scope, which takes precedence.
     #[inline]
-    fn random_index(&self) -> usize {
-        let mut rng = self.state().rng.lock();
-        rng.gen_range(0..SHUFFLING_ARRAY_SIZE)
+    fn random_below(rng: &R, n: usize) -> usize {
+        scoped::seeded_below(n).unwrap_or_else(|| rng.below(n))
     }
 
     #[inline]
-    fn size_classes(&self) -> &SizeClasses<A> {
-        self.state().size_classes.get_or_create(|| {
-            let mut classes =
-                MaybeUninit::<[LazyAtomicCell<A, ShufflingArray<A>>; NUM_SIZE_CLASSES]>::uninit();
+    fn size_classes(&self) -> &SizeClasses<A, N> {
+        self.state()
+            .size_classes
+            .get_or_create(|| SizeClasses::new(self.inner))
+    }
+
+    /// Get the current thread's own size classes, if this allocator uses
+    /// per-thread arrays and they are available right now.
+    ///
+    /// Each thread's streams are derived from the seed and the thread's
+    /// identity, rather than the order in which threads happen to start
+    /// allocating, so that they are the same from run to run.
+    #[inline]
+    fn thread_size_classes(&self) -> Option<&ThreadSizeClasses<A, N, R>> {
+        if !self.config.thread_local_arrays {
+            return None;
+        }
+        let state = self.state();
+        let classes = thread_arrays::get(state.id, || {
+            // Read the epoch before the seed, so that if they change in
+            // between, the streams are rederived on their first use.
+            let epoch = state.epoch.load(Ordering::SeqCst);
+            let seed = state.seed.load(Ordering::SeqCst);
+            let identity = state.thread_names.identify();
+            ThreadSizeClasses::<A, N, R>::create(self.inner, state, seed, identity, epoch)
+        })?;
+        // Safety: the current thread's size classes are only freed when it
+        // exits, which it can't do while it is still using this allocator.
+        let classes = unsafe { &*classes.cast::<ThreadSizeClasses<A, N, R>>() };
+
+        let epoch = state.epoch.load(Ordering::SeqCst);
+        if classes.epoch.get() != epoch {
+            let seed = rng::derive_seed(state.seed.load(Ordering::SeqCst), classes.identity);
+            rng::reseed_streams(&classes.streams, seed);
+            classes.epoch.set(epoch);
+        }
+        Some(classes)
+    }
+
+    /// Get the size classes for the CPU that the current thread is running on,
+    /// if this allocator uses per-CPU arrays and the CPU can be determined.
+    ///
+    /// The thread may migrate to another CPU while it is still using these
+    /// size classes. That's fine, because every operation on a shuffling array
+    /// is a single atomic swap, no matter which CPU it comes from; migration
+    /// just costs some locality.
+    #[inline]
+    fn cpu_size_classes(&self) -> Option<&SizeClasses<A, N>> {
+        if !self.config.per_cpu_arrays {
+            return None;
+        }
+        let cpu = cpu::current()?;
+        let cpus = self.state().cpu_size_classes.get_or_create(|| {
+            let mut cpus =
+                MaybeUninit::<[LazyAtomicCell<A, SizeClasses<A, N>>; MAX_CPUS]>::uninit();
             unsafe {
-                for i in 0..NUM_SIZE_CLASSES {
+                for i in 0..MAX_CPUS {
                     ptr::write(
-                        classes
-                            .as_mut_ptr()
-                            .cast::<LazyAtomicCell<A, ShufflingArray<A>>>()
-                            .offset(i as _),
+                        cpus.as_mut_ptr()
+                            .cast::<LazyAtomicCell<A, SizeClasses<A, N>>>()
+                            .add(i),
                         LazyAtomicCell::new(self.inner),
                     );
                 }
-                SizeClasses(classes.assume_init())
+                cpus.assume_init()
             }
-        })
+        });
+        Some(cpus[cpu % MAX_CPUS].get_or_create(|| SizeClasses::new(self.inner)))
     }
 
+    /// Place a large object of the given layout a random number of pages into
+    /// `block`, which was allocated with the layout's `large::block_layout`,
+    /// timing the random choice with `timer`.
     #[inline]
-    fn shuffling_array(&self, size: usize) -> Option<&ShufflingArray<A>> {
-        let SizeClassInfo { index, size_class } = size_class_info(size)?;
-        let size_classes = self.size_classes();
-        Some(size_classes.0[index].get_or_create(|| ShufflingArray::new(size_class, self.inner)))
+    unsafe fn place_large(&self, block: *mut u8, layout: Layout, timer: TimerRef) -> *mut u8 {
+        if block.is_null() {
+            return ptr::null_mut();
+        }
+        let state = self.state();
+        let pages = if self.is_shuffling() {
+            self.counters().decided();
+            timer.time(Part::Shuffle, || {
+                Self::random_below(
+                    &state.streams[NUM_SIZE_CLASSES],
+                    state.large_object_pages + 1,
+                )
+            })
+        } else {
+            0
+        };
+        large::place(block, layout.size(), pages * large::PAGE_SIZE)
     }
+
+    /// Decide how to handle allocations of the given layout.
+    #[inline]
+    fn route(&self, layout: Layout) -> Route<'_, A, N, R> {
+        let state = self.state();
+        if !state.enabled {
+            return Route::Inner(Bypass::Unshuffled);
+        }
+
+        match self.config.size_classes.lookup(layout.size()) {
+            Some(info) if state.policies[info.index] == ClassPolicy::PassThrough => {
+                Route::Inner(Bypass::Unshuffled)
+            }
+            Some(info) if layout.align() <= MAX_SHUFFLED_ALIGN && !self.is_shuffling() => {
+                let (layout, _) = class_layout(info, layout.align());
+                Route::Unshuffled(layout)
+            }
+            Some(info) if layout.align() <= MAX_SHUFFLED_ALIGN => {
+                let (classes, streams) = match self.thread_size_classes() {
+                    Some(thread) => (&thread.classes, &thread.streams[..]),
+                    None => (
+                        self.cpu_size_classes()
+                            .unwrap_or_else(|| self.size_classes()),
+                        &state.streams[..],
+                    ),
+                };
+                let (array, rng) = self.shuffling_array(classes, streams, info, layout.align());
+                Route::Shuffled(array, rng)
+            }
+            Some(_) => Route::Inner(Bypass::Align),
+            None if state.large_object_pages > 0 => {
+                large::block_layout(layout, state.large_object_pages)
+                    .map_or(Route::Inner(Bypass::Size), Route::Large)
+            }
+            None => Route::Inner(Bypass::Size),
+        }
+    }
+
+    /// Get the shuffling array for the given size class and alignment out of
+    /// `classes`, and its stream out of `streams`, creating and filling the
+    /// array if this is its first use.
+    #[inline]
+    fn shuffling_array<'a>(
+        &'a self,
+        classes: &'a SizeClasses<A, N>,
+        streams: &'a [R],
+        info: SizeClassInfo,
+        align: usize,
+    ) -> (&'a ShufflingArray<A, N>, &'a R) {
+        let state = self.state();
+        let array_size = match state.policies[info.index] {
+            ClassPolicy::Window(n) => n,
+            _ => state.array_size,
+        };
+        let stats = state.stats.class(info.index);
+        let (layout, stream) = class_layout(info, align);
+        let rng = &streams[stream];
+        let array = classes.0[stream].get_or_create(|| {
+            let array =
+                ShufflingArray::new(layout.size(), layout.align(), array_size, stats, self.inner);
+            unsafe {
+                S::fill(&array.slots(rng, state.timers.op(Op::Alloc)));
+            }
+            array
+        });
+        (array, rng)
+    }
+}
+
+/// Get the layout that allocations in the given size class with the given
+/// alignment are rounded up to, and the index of their shuffling array.
+#[inline]
+fn class_layout(info: SizeClassInfo, align: usize) -> (Layout, usize) {
+    let SizeClassInfo { index, size_class } = info;
+    let align_class = align_class(align);
+    let align = mem::align_of::<usize>() << align_class;
+    let layout = unsafe {
+        debug_assert!(Layout::from_size_align(size_class, align).is_ok());
+        Layout::from_size_align_unchecked(size_class, align)
+    };
+    (layout, align_class * SizeClassTable::MAX_CLASSES + index)
 }
 
-unsafe impl<A> GlobalAlloc for ShufflingAllocator<A>
+/// How an allocation is handled.
+enum Route<'a, A, const N: usize, R>
+where
+    A: 'static + GlobalAlloc,
+{
+    /// Shuffle it through a size class's shuffling array, with the given random
+    /// number stream.
+    Shuffled(&'a ShufflingArray<A, N>, &'a R),
+
+    /// Pass it through to the inner allocator, rounded up to its size class's
+    /// layout, because shuffling is turned off for now.
+    Unshuffled(Layout),
+
+    /// Place it at a random page offset within a larger block with this
+    /// layout.
+    Large(Layout),
+
+    /// Pass it straight through to the inner allocator, for the given
+    /// reason.
+    Inner(Bypass),
+}
+
+unsafe impl<A, const N: usize, R, S> GlobalAlloc for ShufflingAllocator<A, N, R, S>
 where
     A: GlobalAlloc,
+    R: ShuffleRng,
+    S: ShuffleStrategy,
 {
     #[inline]
     unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
-        // We only support shuffling reasonably aligned allocations.
-        if layout.align() > mem::align_of::<usize>() {
-            return self.inner.alloc(layout);
-        }
+        let timer = self.timer(Op::Alloc);
+        timer.time(Part::Total, || {
+            let route = self.route(layout);
+            let ptr = match route {
+                // We aren't shuffling this layout (it must be very big or very
+                // aligned, or shuffling is disabled) so just use the inner
+                // allocator.
+                Route::Inner(_) => timer.time(Part::Inner, || self.inner.alloc(layout)),
+                Route::Unshuffled(class_layout) => {
+                    timer.time(Part::Inner, || self.inner.alloc(class_layout))
+                }
 
-        match self.shuffling_array(layout.size()) {
-            // We don't have a shuffling array for this size (it must be fairly
-            // big) so just use the inner allocator.
-            None => self.inner.alloc(layout),
+                // Allocate a padded block and place the object a random number
+                // of pages into it.
+                Route::Large(block_layout) => {
+                    let block = timer.time(Part::Inner, || self.inner.alloc(block_layout));
+                    self.place_large(block, layout, timer)
+                }
 
-            // Choose a random entry from the shuffle array to return, refilling
-            // the entry with a new pointer from the inner allocator.
-            Some(array) => {
-                let replacement_ptr = self.inner.alloc(array.elem_layout());
-                if replacement_ptr.is_null() {
-                    return ptr::null_mut();
+                // Let the shuffle strategy choose an object from the shuffle
+                // array to return (by default, a random entry, which is
+                // refilled with a new pointer from the inner allocator).
+                Route::Shuffled(array, rng) => S::alloc(&array.slots(rng, timer)),
+            };
+            self.record_alloc(&route, layout.size(), ptr);
+            ptr
+        })
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let timer = self.timer(Op::Alloc);
+        timer.time(Part::Total, || {
+            let route = self.route(layout);
+            let ptr = match route {
+                // Let the inner allocator zero the memory however it does best,
+                // for example with lazily zeroed pages straight from the
+                // operating system.
+                Route::Inner(_) => timer.time(Part::Inner, || self.inner.alloc_zeroed(layout)),
+                Route::Unshuffled(class_layout) => {
+                    timer.time(Part::Inner, || self.inner.alloc_zeroed(class_layout))
+                }
+                Route::Large(block_layout) => {
+                    let block = timer.time(Part::Inner, || self.inner.alloc_zeroed(block_layout));
+                    self.place_large(block, layout, timer)
                 }
 
-                let index = self.random_index();
-                array.elems[index].swap(replacement_ptr, Ordering::SeqCst)
-            }
-        }
+                // Entries in the shuffling array may have been used before, so
+                // we have to zero them ourselves. Only the requested bytes need
+                // zeroing, not the whole size class.
+                Route::Shuffled(array, rng) => {
+                    let ptr = S::alloc(&array.slots(rng, timer));
+                    if !ptr.is_null() {
+                        ptr::write_bytes(ptr, 0, layout.size());
+                    }
+                    ptr
+                }
+            };
+            self.record_alloc(&route, layout.size(), ptr);
+            ptr
+        })
     }
 
     #[inline]
@@ -464,22 +1158,90 @@ where
             return;
         }
 
-        if layout.align() > mem::align_of::<usize>() {
-            self.inner.dealloc(ptr, layout);
-            return;
-        }
-
-        match self.shuffling_array(layout.size()) {
+        self.counters().freed(layout.size());
+        let timer = self.timer(Op::Dealloc);
+        timer.time(Part::Total, || match self.route(layout) {
             // No size class for this layout, use the inner allocator directly.
-            None => self.inner.dealloc(ptr, layout),
-
-            // Choose a random entry in the shuffle array to swap this pointer
-            // with, and then deallocate the old entry.
-            Some(array) => {
-                let index = self.random_index();
-                let old_ptr = array.elems[index].swap(ptr, Ordering::SeqCst);
-                self.inner.dealloc(old_ptr, array.elem_layout());
+            Route::Inner(_) => timer.time(Part::Inner, || self.inner.dealloc(ptr, layout)),
+            Route::Unshuffled(class_layout) => {
+                timer.time(Part::Inner, || self.inner.dealloc(ptr, class_layout))
+            }
+
+            // Find and deallocate the block that the object was placed in.
+            Route::Large(block_layout) => {
+                let offset = large::offset(ptr, layout.size());
+                timer.time(Part::Inner, || {
+                    self.inner.dealloc(ptr.sub(offset), block_layout)
+                });
+            }
+
+            // Let the shuffle strategy return the pointer to the shuffle array
+            // (by default, by swapping it with a random entry, and then
+            // deallocating the old entry).
+            Route::Shuffled(array, rng) => {
+                array.stats.free();
+                S::dealloc(&array.slots(rng, timer), ptr);
             }
+        })
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+
+        let new_ptr = match (self.route(layout), self.route(new_layout)) {
+            // Neither layout is shuffled, so let the inner allocator grow or
+            // shrink the allocation in place if it can.
+            (Route::Inner(_), Route::Inner(_)) => self.inner.realloc(ptr, layout, new_size),
+
+            // Both layouts are in the same size class, so the allocation
+            // already has room for the new size.
+            (Route::Shuffled(old_array, _), Route::Shuffled(new_array, _))
+                if ptr::eq(old_array, new_array) =>
+            {
+                ptr
+            }
+
+            // Shuffling is turned off, so let the inner allocator move the
+            // allocation between size classes, in place if it can.
+            (Route::Unshuffled(old_class_layout), Route::Unshuffled(new_class_layout)) => {
+                if old_class_layout == new_class_layout {
+                    ptr
+                } else {
+                    self.inner
+                        .realloc(ptr, old_class_layout, new_class_layout.size())
+                }
+            }
+
+            // Resize the padded block, keeping the object at the same offset
+            // into it, and move the header to the object's new end.
+            (Route::Large(old_block_layout), Route::Large(new_block_layout)) => {
+                let offset = large::offset(ptr, layout.size());
+                let block =
+                    self.inner
+                        .realloc(ptr.sub(offset), old_block_layout, new_block_layout.size());
+                if block.is_null() {
+                    return ptr::null_mut();
+                }
+                let new_ptr = block.add(offset);
+                large::write_header(new_ptr, new_size, offset);
+                new_ptr
+            }
+
+            // Otherwise, the allocation is moving between different kinds of
+            // allocation, and we have to copy it.
+            _ => {
+                let new_ptr = self.alloc(new_layout);
+                if !new_ptr.is_null() {
+                    ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                return new_ptr;
+            }
+        };
+        if !new_ptr.is_null() {
+            self.counters().resized(layout.size(), new_size);
         }
+        new_ptr
     }
 }
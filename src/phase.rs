@@ -0,0 +1,97 @@
+//! Marking phases of a program, so that statistics can be broken down by
+//! phase.
+//!
+//! The current phase is a single process-wide index, which the allocator reads
+//! with a relaxed load whenever it records statistics. Phase names are only
+//! looked up when a phase starts and when statistics are read, never while
+//! allocating.
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "stats")] {
+        /// The maximum number of distinct phases, including the initial
+        /// `"default"` phase.
+        pub(crate) const MAX_PHASES: usize = 16;
+
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        };
+
+        /// The names of the phases that have been started, in the order they
+        /// were first started.
+        static NAMES: Mutex<([&str; MAX_PHASES], usize)> =
+            Mutex::new((["default"; MAX_PHASES], 1));
+
+        /// The index of the current phase in `NAMES`.
+        static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+        /// Start a new phase of the program.
+        ///
+        /// From now on, every `ShufflingAllocator`'s statistics are attributed
+        /// to the phase named `name`, on all threads, until the next call to
+        /// `phase`. Each phase's statistics are reported in
+        /// [`Stats::phases`](./struct.Stats.html#method.phases), and returning to
+        /// a phase that was started before adds to its statistics. Before the
+        /// first call, the program is in a phase named `"default"`.
+        ///
+        /// Phases are only tracked with the `stats` cargo feature; without it,
+        /// this does nothing.
+        ///
+        /// # Panics
+        ///
+        /// Panics if more than 16 distinct phases are started.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use shuffling_allocator::ShufflingAllocator;
+        /// use std::alloc::System;
+        ///
+        /// #[global_allocator]
+        /// static SHUFFLED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+        ///
+        /// shuffling_allocator::phase("warmup");
+        /// let input: Vec<u64> = (0..1000).collect();
+        ///
+        /// shuffling_allocator::phase("measure");
+        /// let output: Vec<Box<u64>> = input.iter().copied().map(Box::new).collect();
+        ///
+        /// # #[cfg(feature = "stats")]
+        /// for phase in SHUFFLED.stats().phases() {
+        ///     println!("{}: {} allocations", phase.name, phase.allocs);
+        /// }
+        /// ```
+        pub fn phase(name: &'static str) {
+            let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+            let (names, len) = &mut *names;
+            let index = match names[..*len].iter().position(|&n| n == name) {
+                Some(index) => index,
+                None => {
+                    assert!(*len < MAX_PHASES, "too many phases");
+                    names[*len] = name;
+                    *len += 1;
+                    *len - 1
+                }
+            };
+            CURRENT.store(index, Ordering::Relaxed);
+        }
+
+        /// The index of the current phase.
+        #[inline]
+        pub(crate) fn current() -> usize {
+            CURRENT.load(Ordering::Relaxed)
+        }
+
+        /// The names of every phase that has been started so far.
+        pub(crate) fn names() -> ([&'static str; MAX_PHASES], usize) {
+            *NAMES.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    } else {
+        /// Start a new phase of the program.
+        ///
+        /// Phases are only tracked with the `stats` cargo feature; without it,
+        /// this does nothing. See the feature's documentation for details.
+        #[inline]
+        pub fn phase(_name: &'static str) {}
+    }
+}
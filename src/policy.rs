@@ -0,0 +1,56 @@
+use std::ops::RangeInclusive;
+
+/// How allocations in a size class are handled.
+///
+/// Set with
+/// [`ShufflingAllocatorBuilder::size_class_policy`](./struct.ShufflingAllocatorBuilder.html#method.size_class_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClassPolicy {
+    /// Shuffle allocations in the size class, using the allocator's array size.
+    /// This is the default.
+    Shuffle,
+
+    /// Pass allocations in the size class straight through to the inner
+    /// allocator.
+    PassThrough,
+
+    /// Shuffle allocations in the size class, using only the first `n`
+    /// entries of its shuffling array, rather than the allocator's array size.
+    Window(usize),
+}
+
+/// The maximum number of calls to `size_class_policy` for one allocator.
+const MAX_RULES: usize = 16;
+
+/// The policies chosen for ranges of sizes, in the order they were chosen.
+#[derive(Clone, Copy)]
+pub(crate) struct PolicyRules {
+    rules: [(usize, usize, ClassPolicy); MAX_RULES],
+    len: usize,
+}
+
+impl PolicyRules {
+    pub const fn new() -> Self {
+        PolicyRules {
+            rules: [(0, 0, ClassPolicy::Shuffle); MAX_RULES],
+            len: 0,
+        }
+    }
+
+    pub const fn with(mut self, sizes: RangeInclusive<usize>, policy: ClassPolicy) -> Self {
+        assert!(self.len < MAX_RULES, "too many size class policies");
+        self.rules[self.len] = (*sizes.start(), *sizes.end(), policy);
+        self.len += 1;
+        self
+    }
+
+    /// Get the policy for the size class of `size` bytes. Later rules take
+    /// precedence over earlier ones.
+    pub fn policy(&self, size: usize) -> ClassPolicy {
+        self.rules[..self.len]
+            .iter()
+            .rev()
+            .find(|&&(start, end, _)| start <= size && size <= end)
+            .map_or(ClassPolicy::Shuffle, |&(_, _, policy)| policy)
+    }
+}
@@ -0,0 +1,414 @@
+#[cfg(feature = "stats")]
+use crate::LockContention;
+use crate::{stats::LockCounters, NUM_SIZE_CLASSES};
+use rand::{RngCore, SeedableRng};
+use std::{
+    cell::UnsafeCell,
+    hint, iter,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// A random number generator for making shuffling decisions.
+///
+/// `ShufflingAllocator`'s `R` parameter chooses which generator it uses. The
+/// built-in generators are [`SplitMix64`], the default, [`WyRand`],
+/// [`Xorshift64`], and [`SmallRng`]. None of them are cryptographically secure,
+/// since randomizing heap layout doesn't call for it.
+///
+/// Generators are shared between every thread that allocates, so `next_u64`
+/// takes `&self`, and should avoid locking if possible. Their state can be
+/// saved and restored, which is how
+/// [`ShufflingAllocator::rng_snapshot`](./struct.ShufflingAllocator.html#method.rng_snapshot)
+/// and
+/// [`ShufflingAllocator::restore_rng`](./struct.ShufflingAllocator.html#method.restore_rng)
+/// work.
+///
+/// # Example
+///
+/// A generator that cycles through a fixed sequence, for experiments:
+///
+/// ```
+/// use shuffling_allocator::{ShuffleRng, ShufflingAllocator};
+/// use std::alloc::System;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+///
+/// struct Cycle(AtomicU64);
+///
+/// impl ShuffleRng for Cycle {
+///     type State = u64;
+///
+///     fn from_seed(seed: u64) -> Self {
+///         Cycle(AtomicU64::new(seed))
+///     }
+///
+///     fn next_u64(&self) -> u64 {
+///         // Spread the counter across the whole range, since `below` uses
+///         // the high bits.
+///         self.0.fetch_add(1, Ordering::Relaxed).wrapping_mul(u64::MAX / 7)
+///     }
+///
+///     fn save(&self) -> u64 {
+///         self.0.load(Ordering::Relaxed)
+///     }
+///
+///     fn restore(&self, state: &u64) {
+///         self.0.store(*state, Ordering::Relaxed);
+///     }
+/// }
+///
+/// static CYCLING_SYSTEM_ALLOC: ShufflingAllocator<System, 256, Cycle> =
+///     ShufflingAllocator::new(&System);
+/// ```
+pub trait ShuffleRng: Send + Sync + Sized + 'static {
+    /// A saved copy of the generator's state.
+    type State: Clone + Send + Sync + 'static;
+
+    /// Create a generator from the given seed.
+    ///
+    /// This is called while the allocator is initializing itself, so it must
+    /// not allocate.
+    fn from_seed(seed: u64) -> Self;
+
+    /// Get the next random number.
+    fn next_u64(&self) -> u64;
+
+    /// Save the generator's current state.
+    fn save(&self) -> Self::State;
+
+    /// Restore a previously saved state, so that the generator produces the
+    /// same numbers that it did after the state was saved.
+    fn restore(&self, state: &Self::State);
+
+    /// Start the generator over from a new seed, as if it had been created
+    /// with `from_seed`.
+    fn reseed(&self, seed: u64) {
+        self.restore(&Self::from_seed(seed).save());
+    }
+
+    /// Get a random number in `0..n`.
+    ///
+    /// By default, this maps `next_u64`'s output onto the range with a
+    /// multiply and shift, rather than rejection sampling. The resulting bias
+    /// is at most `n / 2^64`, which is immaterial for the small ranges that
+    /// shuffling draws from.
+    #[inline]
+    fn below(&self, n: usize) -> usize {
+        reduce(self.next_u64(), n)
+    }
+
+    /// How often threads have had to wait for this generator's lock, if it
+    /// has one.
+    ///
+    /// This is only available with the `stats` cargo feature, and is reported
+    /// in
+    /// [`Stats::rng_lock`](./struct.Stats.html#structfield.rng_lock). By
+    /// default, it reports no contention, as for the lock-free generators.
+    #[cfg(feature = "stats")]
+    fn contention(&self) -> LockContention {
+        LockContention::default()
+    }
+}
+
+/// Map a random 64-bit number onto `0..n`.
+#[inline]
+pub(crate) fn reduce(x: u64, n: usize) -> usize {
+    ((u128::from(x) * n as u128) >> 64) as usize
+}
+
+/// The [SplitMix64](https://prng.di.unimi.it/splitmix64.c) generator.
+///
+/// This is the default generator. Its state is a counter that advances by a
+/// fixed odd increment, and each output is a hash of the counter. Advancing the
+/// state is a single atomic add, so any number of threads can draw from it
+/// without locking, and a single thread drawing from a generator with a fixed
+/// seed always sees the same sequence of outputs.
+pub struct SplitMix64 {
+    state: AtomicU64,
+}
+
+const SPLITMIX64_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// SplitMix64's output function.
+#[inline]
+fn splitmix64_mix(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Take one step of a SplitMix64 generator with the given state, returning
+/// the output and the new state.
+#[inline]
+pub(crate) fn splitmix64_step(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(SPLITMIX64_GAMMA);
+    (splitmix64_mix(state), state)
+}
+
+impl ShuffleRng for SplitMix64 {
+    type State = u64;
+
+    fn from_seed(seed: u64) -> Self {
+        SplitMix64 {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    #[inline]
+    fn next_u64(&self) -> u64 {
+        let state = self.state.fetch_add(SPLITMIX64_GAMMA, Ordering::Relaxed);
+        splitmix64_step(state).0
+    }
+
+    fn save(&self) -> u64 {
+        self.state.load(Ordering::Relaxed)
+    }
+
+    fn restore(&self, state: &u64) {
+        self.state.store(*state, Ordering::Relaxed);
+    }
+}
+
+/// Derive the seed for an independent set of streams, identified by `key`,
+/// from the master `seed`.
+pub(crate) fn derive_seed(seed: u64, key: u64) -> u64 {
+    splitmix64_mix(seed ^ splitmix64_mix(key))
+}
+
+/// Create `K` independent random number streams from the master `seed`.
+///
+/// Stream `i` is seeded with the `i`th output of SplitMix64 seeded with `seed`,
+/// so each stream's sequence depends only on the master seed and its index.
+pub(crate) fn streams<R: ShuffleRng, const K: usize>(seed: u64) -> [R; K] {
+    let mut seeds = stream_seeds(seed);
+    let mut streams = MaybeUninit::<[R; K]>::uninit();
+    unsafe {
+        for i in 0..K {
+            ptr::write(
+                streams.as_mut_ptr().cast::<R>().add(i),
+                R::from_seed(seeds.next().unwrap()),
+            );
+        }
+        streams.assume_init()
+    }
+}
+
+/// Reseed `streams` from the master `seed`, as if they had just been created
+/// by `streams`.
+pub(crate) fn reseed_streams<R: ShuffleRng>(streams: &[R], seed: u64) {
+    for (stream, seed) in streams.iter().zip(stream_seeds(seed)) {
+        stream.reseed(seed);
+    }
+}
+
+/// The seeds of each stream derived from the master `seed`.
+fn stream_seeds(seed: u64) -> impl Iterator<Item = u64> {
+    let seeds = SplitMix64::from_seed(seed);
+    iter::repeat_with(move || seeds.next_u64())
+}
+
+/// A saved copy of the state of a `ShufflingAllocator`'s random number
+/// generators.
+///
+/// Created by
+/// [`ShufflingAllocator::rng_snapshot`](./struct.ShufflingAllocator.html#method.rng_snapshot),
+/// and restored with
+/// [`ShufflingAllocator::restore_rng`](./struct.ShufflingAllocator.html#method.restore_rng).
+pub struct RngSnapshot<R: ShuffleRng> {
+    pub(crate) seed: u64,
+    pub(crate) streams: [R::State; NUM_SIZE_CLASSES + 1],
+}
+
+impl<R: ShuffleRng> Clone for RngSnapshot<R> {
+    fn clone(&self) -> Self {
+        RngSnapshot {
+            seed: self.seed,
+            streams: self.streams.clone(),
+        }
+    }
+}
+
+impl<R: ShuffleRng> RngSnapshot<R> {
+    /// The seed that the allocator was using when this snapshot was taken.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+/// The [wyrand](https://github.com/wangyi-fudan/wyhash) generator.
+///
+/// Like [`SplitMix64`], its state is a counter, so it is lock-free, but its
+/// output function is a single wide multiply, which is cheaper.
+pub struct WyRand {
+    state: AtomicU64,
+}
+
+const WYRAND_INCREMENT: u64 = 0xa076_1d64_78bd_642f;
+
+impl ShuffleRng for WyRand {
+    type State = u64;
+
+    fn from_seed(seed: u64) -> Self {
+        WyRand {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    #[inline]
+    fn next_u64(&self) -> u64 {
+        let state = self
+            .state
+            .fetch_add(WYRAND_INCREMENT, Ordering::Relaxed)
+            .wrapping_add(WYRAND_INCREMENT);
+        let t = u128::from(state) * u128::from(state ^ 0xe703_7ed1_a0b4_28db);
+        ((t >> 64) ^ t) as u64
+    }
+
+    fn save(&self) -> u64 {
+        self.state.load(Ordering::Relaxed)
+    }
+
+    fn restore(&self, state: &u64) {
+        self.state.store(*state, Ordering::Relaxed);
+    }
+}
+
+/// Marsaglia's [xorshift](https://www.jstatsoft.org/article/view/v008i14)
+/// generator, with 64 bits of state.
+///
+/// Each step depends on the previous state, so concurrent threads update it
+/// with a compare-and-swap loop. It is lock-free, but slows down more than
+/// [`SplitMix64`] when many threads are allocating at once.
+pub struct Xorshift64 {
+    state: AtomicU64,
+}
+
+impl ShuffleRng for Xorshift64 {
+    type State = u64;
+
+    fn from_seed(seed: u64) -> Self {
+        // An all-zero state would only ever produce zeroes, and SplitMix64's
+        // output function maps only one seed to zero.
+        let state = match splitmix64_mix(seed) {
+            0 => SPLITMIX64_GAMMA,
+            state => state,
+        };
+        Xorshift64 {
+            state: AtomicU64::new(state),
+        }
+    }
+
+    #[inline]
+    fn next_u64(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            let next = step(state);
+            match self.state.compare_exchange_weak(
+                state,
+                next,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return next,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    fn save(&self) -> u64 {
+        self.state.load(Ordering::Relaxed)
+    }
+
+    fn restore(&self, state: &u64) {
+        self.state.store(*state, Ordering::Relaxed);
+    }
+}
+
+/// Any of `rand`'s seedable generators, shared between threads with a lock.
+///
+/// The platform's mutexes must be allocated before they can be used, and
+/// generators are created without an allocator, so this is a spin lock. It is
+/// only held for a single step of the generator. With the `stats` feature,
+/// it counts how often threads have to wait for it, and for how long.
+pub struct Locked<R> {
+    locked: AtomicBool,
+    contention: LockCounters,
+    rng: UnsafeCell<R>,
+}
+
+unsafe impl<R: Send> Sync for Locked<R> {}
+
+impl<R> Locked<R> {
+    /// Run `f` with the lock held.
+    #[inline]
+    fn with<T>(&self, f: impl FnOnce(&mut R) -> T) -> T {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.wait();
+        }
+        let x = f(unsafe { &mut *self.rng.get() });
+        self.locked.store(false, Ordering::Release);
+        x
+    }
+
+    /// Spin until the lock is free, and take it.
+    #[cold]
+    fn wait(&self) {
+        self.contention.wait(|| {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                hint::spin_loop();
+            }
+        });
+    }
+}
+
+impl<R> ShuffleRng for Locked<R>
+where
+    R: RngCore + SeedableRng + Clone + Send + Sync + 'static,
+{
+    type State = R;
+
+    fn from_seed(seed: u64) -> Self {
+        Locked {
+            locked: AtomicBool::new(false),
+            contention: LockCounters::new(),
+            rng: UnsafeCell::new(R::seed_from_u64(seed)),
+        }
+    }
+
+    #[inline]
+    fn next_u64(&self) -> u64 {
+        self.with(|rng| rng.next_u64())
+    }
+
+    fn save(&self) -> R {
+        self.with(|rng| rng.clone())
+    }
+
+    fn restore(&self, state: &R) {
+        self.with(|rng| rng.clone_from(state));
+    }
+
+    #[cfg(feature = "stats")]
+    fn contention(&self) -> LockContention {
+        self.contention.snapshot()
+    }
+}
+
+/// `rand`'s [`SmallRng`](rand::rngs::SmallRng), shared between threads with a
+/// lock.
+pub type SmallRng = Locked<rand::rngs::SmallRng>;
@@ -0,0 +1,114 @@
+//! Scoped, thread-local overrides of how the allocator behaves.
+//!
+//! These apply to every `ShufflingAllocator` that is used on the current
+//! thread. They are plain `Cell`s with `const` initializers, so accessing them
+//! never allocates and is safe from inside the allocator.
+
+use crate::rng;
+use std::cell::Cell;
+
+thread_local! {
+    /// The state of the current `with_seed` scope's random number stream.
+    static SEEDED_STREAM: Cell<Option<u64>> = const { Cell::new(None) };
+
+    /// Whether the current thread is inside an `unshuffled` scope.
+    static UNSHUFFLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Restores a thread-local to its previous value when dropped, including
+/// when unwinding.
+struct Restore<T: Copy + 'static> {
+    key: &'static std::thread::LocalKey<Cell<T>>,
+    previous: T,
+}
+
+impl<T: Copy + 'static> Restore<T> {
+    fn replace(key: &'static std::thread::LocalKey<Cell<T>>, value: T) -> Self {
+        let previous = key.with(|cell| cell.replace(value));
+        Restore { key, previous }
+    }
+}
+
+impl<T: Copy + 'static> Drop for Restore<T> {
+    fn drop(&mut self) {
+        self.key.with(|cell| cell.set(self.previous));
+    }
+}
+
+/// Make every shuffling decision on the current thread while running `f` come
+/// from a dedicated stream of random numbers seeded with `seed`.
+///
+/// This pins down the layout of whatever `f` allocates, while the rest of the
+/// program stays randomized. It applies to every `ShufflingAllocator` used on
+/// this thread, and to all size classes, which share the one stream. The
+/// stream is a SplitMix64 generator, whatever the allocator's own generator is.
+///
+/// When `f` returns, or panics, the thread goes back to the stream it was using
+/// before, so scopes can be nested.
+///
+/// # Example
+///
+/// ```
+/// use shuffling_allocator::ShufflingAllocator;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+///
+/// let pinned = shuffling_allocator::with_seed(42, || {
+///     (0..100).map(Box::new).collect::<Vec<_>>()
+/// });
+/// ```
+pub fn with_seed<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore::replace(&SEEDED_STREAM, Some(seed));
+    f()
+}
+
+/// Turn shuffling off on the current thread while running `f`.
+///
+/// This is the thread-local, scoped version of
+/// [`ShufflingAllocator::set_enabled`](./struct.ShufflingAllocator.html#method.set_enabled),
+/// and the same caveats apply. It applies to every `ShufflingAllocator` used
+/// on this thread. When `f` returns, or panics, shuffling on this thread goes
+/// back to how it was before.
+///
+/// # Example
+///
+/// ```
+/// use shuffling_allocator::ShufflingAllocator;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+///
+/// // Set up the benchmark's input without shuffling...
+/// let input: Vec<u64> = shuffling_allocator::unshuffled(|| (0..1000).collect());
+///
+/// // ...and shuffle the part being measured.
+/// let output: Vec<Box<u64>> = input.iter().copied().map(Box::new).collect();
+/// ```
+pub fn unshuffled<T>(f: impl FnOnce() -> T) -> T {
+    let _restore = Restore::replace(&UNSHUFFLED, true);
+    f()
+}
+
+/// Whether the current thread is inside an `unshuffled` scope.
+#[inline]
+pub(crate) fn is_unshuffled() -> bool {
+    UNSHUFFLED.try_with(Cell::get).unwrap_or(false)
+}
+
+/// Get a random number in `0..n` from the current `with_seed` scope's stream,
+/// or `None` if the current thread isn't in one.
+#[inline]
+pub(crate) fn seeded_below(n: usize) -> Option<usize> {
+    SEEDED_STREAM
+        .try_with(|stream| {
+            let state = stream.get()?;
+            let (x, state) = rng::splitmix64_step(state);
+            stream.set(Some(state));
+            Some(rng::reduce(x, n))
+        })
+        .ok()
+        .flatten()
+}
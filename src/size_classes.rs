@@ -0,0 +1,171 @@
+use std::mem;
+
+/// Every size class must be a multiple of this, which is also the granularity
+/// of the lookup table.
+const GRANULE: usize = mem::size_of::<usize>();
+
+const LOOKUP_LEN: usize = SizeClassTable::MAX_SIZE / GRANULE + 1;
+
+/// A table of size classes.
+///
+/// Each size class gets its own shuffling array, and allocations are rounded up
+/// to the smallest size class that fits them. Allocations larger than the
+/// largest size class are not shuffled.
+///
+/// Tables are built at compile time, along with an index that makes finding an
+/// allocation's size class a single table lookup.
+///
+/// # Example
+///
+/// Use size classes that exactly match an inner allocator's bins:
+///
+/// ```
+/// use shuffling_allocator::{ShufflingAllocator, SizeClassTable};
+/// use std::alloc::System;
+///
+/// static BINS: SizeClassTable = SizeClassTable::new(&[
+///     16, 32, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512,
+/// ]);
+///
+/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System> =
+///     ShufflingAllocator::builder(&System).size_classes(&BINS).build();
+/// ```
+pub struct SizeClassTable {
+    sizes: [usize; SizeClassTable::MAX_CLASSES],
+    len: usize,
+    // `lookup[i]` is the index of the size class for allocations of
+    // `(i - 1) * GRANULE + 1 ..= i * GRANULE` bytes.
+    lookup: [u8; LOOKUP_LEN],
+}
+
+impl SizeClassTable {
+    /// The maximum number of size classes in a table.
+    pub const MAX_CLASSES: usize = 64;
+
+    /// The maximum size of a size class.
+    pub const MAX_SIZE: usize = 32 * 1024;
+
+    /// The default size classes.
+    ///
+    /// There are 32 classes, starting at the word size and growing by one word
+    /// at a time, with the stride doubling every four classes. On 64-bit
+    /// targets, the largest class is 7144 bytes.
+    pub const DEFAULT: SizeClassTable = SizeClassTable::new(&default_sizes());
+
+    /// Create a table from the given size classes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more than `MAX_CLASSES` sizes, or if the sizes are
+    /// not strictly increasing, non-zero multiples of the word size no greater
+    /// than `MAX_SIZE`. When the table is built in a `static` or `const`, these
+    /// are compile-time errors.
+    pub const fn new(sizes: &[usize]) -> Self {
+        assert!(sizes.len() <= Self::MAX_CLASSES, "too many size classes");
+
+        let mut table = SizeClassTable {
+            sizes: [0; Self::MAX_CLASSES],
+            len: sizes.len(),
+            lookup: [0; LOOKUP_LEN],
+        };
+
+        let mut i = 0;
+        while i < sizes.len() {
+            let size = sizes[i];
+            assert!(size > 0, "size classes must be non-zero");
+            assert!(
+                size % GRANULE == 0,
+                "size classes must be multiples of the word size"
+            );
+            assert!(size <= Self::MAX_SIZE, "size class is too large");
+            assert!(
+                i == 0 || sizes[i - 1] < size,
+                "size classes must be strictly increasing"
+            );
+            table.sizes[i] = size;
+            i += 1;
+        }
+
+        // Size classes are multiples of the granule, so the smallest class that
+        // fits `i * GRANULE` bytes also fits everything else in slot `i`.
+        let mut class = 0;
+        let mut slot = 0;
+        while slot < LOOKUP_LEN {
+            while class < sizes.len() && sizes[class] < slot * GRANULE {
+                class += 1;
+            }
+            if class == sizes.len() {
+                break;
+            }
+            table.lookup[slot] = class as u8;
+            slot += 1;
+        }
+
+        table
+    }
+
+    /// The number of size classes in this table.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether this table has no size classes, and therefore shuffles nothing.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The size classes in this table, from smallest to largest.
+    pub const fn sizes(&self) -> &[usize] {
+        self.sizes.split_at(self.len).0
+    }
+
+    /// The size class that an allocation of `size` bytes is rounded up to, or
+    /// `None` if it is larger than every size class.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use shuffling_allocator::SizeClassTable;
+    ///
+    /// let table = SizeClassTable::new(&[16, 32, 64]);
+    /// assert_eq!(table.size_class(1), Some(16));
+    /// assert_eq!(table.size_class(33), Some(64));
+    /// assert_eq!(table.size_class(65), None);
+    /// ```
+    pub fn size_class(&self, size: usize) -> Option<usize> {
+        self.lookup(size).map(|info| info.size_class)
+    }
+
+    #[inline]
+    pub(crate) fn lookup(&self, size: usize) -> Option<SizeClassInfo> {
+        if self.len == 0 || size > self.sizes[self.len - 1] {
+            return None;
+        }
+        let index = self.lookup[size.div_ceil(GRANULE)] as usize;
+        Some(SizeClassInfo {
+            index,
+            size_class: self.sizes[index],
+        })
+    }
+}
+
+pub(crate) struct SizeClassInfo {
+    pub index: usize,
+    pub size_class: usize,
+}
+
+const fn default_sizes() -> [usize; 32] {
+    let mut sizes = [0; 32];
+    let mut size_class = mem::size_of::<usize>();
+    let mut stride = mem::size_of::<usize>();
+    let mut i = 0;
+    while i < sizes.len() {
+        if i > 0 && i % 4 == 0 {
+            stride *= 2;
+        }
+        sizes[i] = size_class;
+        size_class += stride;
+        i += 1;
+    }
+    sizes
+}
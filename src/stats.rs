@@ -0,0 +1,445 @@
+//! Counting what the allocator does, for `ShufflingAllocator::stats`.
+//!
+//! The counters are updated with relaxed atomics on the allocation paths.
+//! Without the `stats` feature, they are zero-sized and every update is a
+//! no-op, so they cost nothing.
+
+/// Why an allocation wasn't shuffled.
+#[derive(Clone, Copy)]
+pub(crate) enum Bypass {
+    /// It is larger than the largest size class.
+    Size,
+    /// It is aligned to more than `MAX_SHUFFLED_ALIGN`.
+    Align,
+    /// Shuffling is turned off, or its size class passes allocations through.
+    Unshuffled,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "stats")] {
+        use crate::{phase::{self, MAX_PHASES}, SizeClassTable};
+        use std::{
+            convert::TryFrom,
+            sync::atomic::{AtomicIsize, AtomicU64, AtomicUsize, Ordering},
+            time::Instant,
+        };
+
+        /// A snapshot of a `ShufflingAllocator`'s statistics.
+        ///
+        /// Created by
+        /// [`ShufflingAllocator::stats`](./struct.ShufflingAllocator.html#method.stats).
+        #[derive(Clone, Copy, Debug)]
+        pub struct Stats {
+            classes: [ClassStats; SizeClassTable::MAX_CLASSES],
+            len: usize,
+            phases: [PhaseStats; MAX_PHASES],
+            num_phases: usize,
+            /// The number of allocations that weren't shuffled because they
+            /// are larger than the largest size class. Large objects whose
+            /// placement is randomized at page granularity don't count.
+            pub bypassed_size: u64,
+            /// The number of allocations that weren't shuffled because they
+            /// are aligned to more than 64 bytes.
+            pub bypassed_align: u64,
+            /// The number of allocations that weren't shuffled because
+            /// shuffling was turned off, or because their size class's policy
+            /// is to pass them through.
+            pub unshuffled: u64,
+            /// The number of bytes currently allocated, as requested by the
+            /// program rather than rounded up to size classes.
+            pub live_bytes: usize,
+            /// The most bytes that were ever allocated at once.
+            pub peak_bytes: usize,
+            /// How much threads had to wait for the random number generators'
+            /// locks, for generators that lock.
+            pub rng_lock: LockContention,
+        }
+
+        impl Stats {
+            /// Statistics for each size class, from smallest to largest.
+            ///
+            /// Each size class's statistics cover all of its shuffling arrays,
+            /// for every alignment, thread, and CPU.
+            pub fn classes(&self) -> &[ClassStats] {
+                &self.classes[..self.len]
+            }
+
+            /// The number of bytes of objects that are sitting in shuffling
+            /// arrays, waiting to be handed out or returned to the inner
+            /// allocator.
+            pub fn parked_bytes(&self) -> usize {
+                self.classes().iter().map(|c| c.parked * c.size).sum()
+            }
+
+            /// Statistics for each phase of the program that has been started
+            /// with [`phase`](./fn.phase.html), in the order they were first
+            /// started, beginning with the `"default"` phase.
+            pub fn phases(&self) -> &[PhaseStats] {
+                &self.phases[..self.num_phases]
+            }
+
+            /// Statistics for the phase named `name`, if it has been started.
+            pub fn phase(&self, name: &str) -> Option<&PhaseStats> {
+                self.phases().iter().find(|p| p.name == name)
+            }
+        }
+
+        /// A snapshot of one size class's statistics.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct ClassStats {
+            /// The size class, in bytes.
+            pub size: usize,
+            /// The number of shuffled allocations in this size class.
+            pub allocs: u64,
+            /// The number of shuffled frees in this size class.
+            pub frees: u64,
+            /// The number of objects sitting in this size class's shuffling
+            /// arrays.
+            pub parked: usize,
+        }
+
+        /// A snapshot of the statistics for one phase of the program.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct PhaseStats {
+            /// The phase's name, as passed to [`phase`](./fn.phase.html).
+            pub name: &'static str,
+            /// The number of allocations during this phase, whether or not they
+            /// were shuffled.
+            pub allocs: u64,
+            /// The number of frees during this phase.
+            pub frees: u64,
+            /// The number of allocations during this phase that weren't
+            /// shuffled, for any reason.
+            pub bypassed: u64,
+            /// The number of bytes allocated during this phase, including
+            /// growth from resizing allocations in place.
+            pub bytes_allocated: u64,
+            /// The number of bytes freed during this phase, including shrinkage
+            /// from resizing allocations in place.
+            pub bytes_freed: u64,
+            /// The number of random numbers chosen during this phase to decide
+            /// where objects go.
+            pub decisions: u64,
+        }
+
+        /// How often a lock was contended, and how long threads waited for it.
+        ///
+        /// See [`ShuffleRng::contention`](./trait.ShuffleRng.html#method.contention).
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct LockContention {
+            /// The number of times that a thread found the lock held, and had
+            /// to wait for it.
+            pub contended: u64,
+            /// The total time that threads spent waiting for the lock, in
+            /// nanoseconds.
+            pub wait_nanos: u64,
+        }
+
+        /// Counts how often a lock is contended.
+        pub(crate) struct LockCounters {
+            contended: AtomicU64,
+            wait_nanos: AtomicU64,
+        }
+
+        impl LockCounters {
+            pub const fn new() -> Self {
+                LockCounters {
+                    contended: AtomicU64::new(0),
+                    wait_nanos: AtomicU64::new(0),
+                }
+            }
+
+            /// Wait for a contended lock by calling `wait`, counting the
+            /// contention and timing the wait.
+            #[inline]
+            pub fn wait(&self, wait: impl FnOnce()) {
+                let start = Instant::now();
+                wait();
+                let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+                self.contended.fetch_add(1, Ordering::Relaxed);
+                self.wait_nanos.fetch_add(nanos, Ordering::Relaxed);
+            }
+
+            pub fn snapshot(&self) -> LockContention {
+                LockContention {
+                    contended: self.contended.load(Ordering::Relaxed),
+                    wait_nanos: self.wait_nanos.load(Ordering::Relaxed),
+                }
+            }
+        }
+
+        pub(crate) struct ClassCounters {
+            allocs: AtomicU64,
+            frees: AtomicU64,
+            // Updated separately from `allocs` and `frees`, so it may briefly
+            // dip below zero.
+            parked: AtomicIsize,
+        }
+
+        struct PhaseCounters {
+            allocs: AtomicU64,
+            frees: AtomicU64,
+            bypassed: AtomicU64,
+            bytes_allocated: AtomicU64,
+            bytes_freed: AtomicU64,
+            decisions: AtomicU64,
+        }
+
+        /// The counters for all of an allocator's size classes and phases.
+        pub(crate) struct Counters {
+            classes: [ClassCounters; SizeClassTable::MAX_CLASSES],
+            phases: [PhaseCounters; MAX_PHASES],
+            bypassed_size: AtomicU64,
+            bypassed_align: AtomicU64,
+            unshuffled: AtomicU64,
+            live_bytes: AtomicUsize,
+            peak_bytes: AtomicUsize,
+        }
+
+        impl Counters {
+            pub fn new() -> Self {
+                Counters {
+                    classes: [const {
+                        ClassCounters {
+                            allocs: AtomicU64::new(0),
+                            frees: AtomicU64::new(0),
+                            parked: AtomicIsize::new(0),
+                        }
+                    }; SizeClassTable::MAX_CLASSES],
+                    phases: [const {
+                        PhaseCounters {
+                            allocs: AtomicU64::new(0),
+                            frees: AtomicU64::new(0),
+                            bypassed: AtomicU64::new(0),
+                            bytes_allocated: AtomicU64::new(0),
+                            bytes_freed: AtomicU64::new(0),
+                            decisions: AtomicU64::new(0),
+                        }
+                    }; MAX_PHASES],
+                    bypassed_size: AtomicU64::new(0),
+                    bypassed_align: AtomicU64::new(0),
+                    unshuffled: AtomicU64::new(0),
+                    live_bytes: AtomicUsize::new(0),
+                    peak_bytes: AtomicUsize::new(0),
+                }
+            }
+
+            /// Get the counters for the size class at `index` in the table.
+            pub fn class(&self, index: usize) -> ClassRef {
+                ClassRef {
+                    counters: self,
+                    index,
+                }
+            }
+
+            /// Get the counters for the current phase.
+            #[inline]
+            fn phase(&self) -> &PhaseCounters {
+                &self.phases[phase::current()]
+            }
+
+            #[inline]
+            pub fn bypassed(&self, bypass: Bypass) {
+                let counter = match bypass {
+                    Bypass::Size => &self.bypassed_size,
+                    Bypass::Align => &self.bypassed_align,
+                    Bypass::Unshuffled => &self.unshuffled,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                self.phase().bypassed.fetch_add(1, Ordering::Relaxed);
+            }
+
+            #[inline]
+            pub fn allocated(&self, bytes: usize) {
+                let phase = self.phase();
+                phase.allocs.fetch_add(1, Ordering::Relaxed);
+                phase.bytes_allocated.fetch_add(bytes as u64, Ordering::Relaxed);
+                self.grew(bytes);
+            }
+
+            #[inline]
+            pub fn freed(&self, bytes: usize) {
+                let phase = self.phase();
+                phase.frees.fetch_add(1, Ordering::Relaxed);
+                phase.bytes_freed.fetch_add(bytes as u64, Ordering::Relaxed);
+                self.live_bytes.fetch_sub(bytes, Ordering::Relaxed);
+            }
+
+            /// An allocation was resized in place from `old` to `new` bytes.
+            #[inline]
+            pub fn resized(&self, old: usize, new: usize) {
+                let phase = self.phase();
+                if new >= old {
+                    phase.bytes_allocated.fetch_add((new - old) as u64, Ordering::Relaxed);
+                    self.grew(new - old);
+                } else {
+                    phase.bytes_freed.fetch_add((old - new) as u64, Ordering::Relaxed);
+                    self.live_bytes.fetch_sub(old - new, Ordering::Relaxed);
+                }
+            }
+
+            /// A random number was chosen to decide where an object goes.
+            #[inline]
+            pub fn decided(&self) {
+                self.phase().decisions.fetch_add(1, Ordering::Relaxed);
+            }
+
+            #[inline]
+            fn grew(&self, bytes: usize) {
+                let live = self.live_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+                self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+            }
+
+            pub fn snapshot(&self, table: &SizeClassTable, rng_lock: LockContention) -> Stats {
+                let mut classes = [ClassStats::default(); SizeClassTable::MAX_CLASSES];
+                for ((stats, counters), &size) in
+                    classes.iter_mut().zip(&self.classes).zip(table.sizes())
+                {
+                    *stats = ClassStats {
+                        size,
+                        allocs: counters.allocs.load(Ordering::Relaxed),
+                        frees: counters.frees.load(Ordering::Relaxed),
+                        parked: counters.parked.load(Ordering::Relaxed).max(0) as usize,
+                    };
+                }
+                let (names, num_phases) = phase::names();
+                let mut phases = [PhaseStats::default(); MAX_PHASES];
+                for ((stats, counters), &name) in
+                    phases.iter_mut().zip(&self.phases).zip(&names[..num_phases])
+                {
+                    *stats = PhaseStats {
+                        name,
+                        allocs: counters.allocs.load(Ordering::Relaxed),
+                        frees: counters.frees.load(Ordering::Relaxed),
+                        bypassed: counters.bypassed.load(Ordering::Relaxed),
+                        bytes_allocated: counters.bytes_allocated.load(Ordering::Relaxed),
+                        bytes_freed: counters.bytes_freed.load(Ordering::Relaxed),
+                        decisions: counters.decisions.load(Ordering::Relaxed),
+                    };
+                }
+                Stats {
+                    classes,
+                    len: table.len(),
+                    phases,
+                    num_phases,
+                    bypassed_size: self.bypassed_size.load(Ordering::Relaxed),
+                    bypassed_align: self.bypassed_align.load(Ordering::Relaxed),
+                    unshuffled: self.unshuffled.load(Ordering::Relaxed),
+                    live_bytes: self.live_bytes.load(Ordering::Relaxed),
+                    peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+                    rng_lock,
+                }
+            }
+        }
+
+        /// A handle to one size class's counters, kept by each of its
+        /// shuffling arrays.
+        ///
+        /// The counters live in the allocator's state, which outlives its
+        /// arrays: shared arrays are part of the state, and threads' own arrays
+        /// hold a reference that keeps the state alive until they are freed.
+        #[derive(Clone, Copy)]
+        pub(crate) struct ClassRef {
+            counters: *const Counters,
+            index: usize,
+        }
+
+        unsafe impl Send for ClassRef {}
+        unsafe impl Sync for ClassRef {}
+
+        impl ClassRef {
+            #[inline]
+            fn counters(&self) -> &ClassCounters {
+                unsafe { &(*self.counters).classes[self.index] }
+            }
+
+            /// An object was handed out of the size class's arrays.
+            #[inline]
+            pub fn alloc(self) {
+                let counters = self.counters();
+                counters.allocs.fetch_add(1, Ordering::Relaxed);
+                counters.parked.fetch_sub(1, Ordering::Relaxed);
+            }
+
+            /// An object was freed into the size class's arrays.
+            #[inline]
+            pub fn free(self) {
+                let counters = self.counters();
+                counters.frees.fetch_add(1, Ordering::Relaxed);
+                counters.parked.fetch_add(1, Ordering::Relaxed);
+            }
+
+            /// Objects moved between the inner allocator and the arrays.
+            #[inline]
+            pub fn parked(self, delta: isize) {
+                let counters = self.counters();
+                counters.parked.fetch_add(delta, Ordering::Relaxed);
+            }
+
+            /// A random number was chosen to decide where one of the size
+            /// class's objects goes.
+            #[inline]
+            pub fn decided(self) {
+                unsafe { (*self.counters).decided() }
+            }
+        }
+    } else {
+        pub(crate) struct LockCounters;
+
+        impl LockCounters {
+            pub const fn new() -> Self {
+                LockCounters
+            }
+
+            #[inline]
+            pub fn wait(&self, wait: impl FnOnce()) {
+                wait()
+            }
+        }
+
+        pub(crate) struct Counters;
+
+        impl Counters {
+            pub fn new() -> Self {
+                Counters
+            }
+
+            #[inline]
+            pub fn class(&self, _index: usize) -> ClassRef {
+                ClassRef
+            }
+
+            #[inline]
+            pub fn bypassed(&self, _bypass: Bypass) {}
+
+            #[inline]
+            pub fn allocated(&self, _bytes: usize) {}
+
+            #[inline]
+            pub fn freed(&self, _bytes: usize) {}
+
+            #[inline]
+            pub fn resized(&self, _old: usize, _new: usize) {}
+
+            #[inline]
+            pub fn decided(&self) {}
+        }
+
+        #[derive(Clone, Copy)]
+        pub(crate) struct ClassRef;
+
+        impl ClassRef {
+            #[inline]
+            pub fn alloc(self) {}
+
+            #[inline]
+            pub fn free(self) {}
+
+            #[inline]
+            pub fn parked(self, _delta: isize) {}
+
+            #[inline]
+            pub fn decided(self) {}
+        }
+    }
+}
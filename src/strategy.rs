@@ -0,0 +1,359 @@
+use crate::{
+    latency::{Part, TimerRef},
+    scoped,
+    stats::ClassRef,
+    ShuffleRng,
+};
+use std::{
+    alloc::{handle_alloc_error, GlobalAlloc, Layout},
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+/// An algorithm for shuffling allocations through a size class's shuffling
+/// array.
+///
+/// `ShufflingAllocator`'s `S` parameter chooses which strategy it uses. The
+/// built-in strategies are [`SwapOnAllocAndFree`], the default,
+/// [`SwapOnAlloc`], [`SwapOnFree`], [`BatchRefill`], and [`DelayedReuse`].
+///
+/// Every strategy works on the same storage: the entries of the array, which
+/// hold objects of the size class that the inner allocator has allocated but
+/// that haven't been handed out, and a counter. Strategies are called
+/// concurrently from many threads, so they may only update entries and the
+/// counter atomically, and must be prepared to find an entry empty. Whatever
+/// is left in the entries is returned to the inner allocator when the array is
+/// dropped.
+///
+/// # Example
+///
+/// A strategy that hands out objects in the order that the inner allocator
+/// allocates them, but frees them in a random order:
+///
+/// ```
+/// use shuffling_allocator::{ShuffleRng, ShuffleStrategy, ShufflingAllocator, Slots, SplitMix64};
+/// use std::alloc::{GlobalAlloc, System};
+/// use std::sync::atomic::Ordering;
+///
+/// struct ShuffleFrees;
+///
+/// impl ShuffleStrategy for ShuffleFrees {
+///     unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+///         slots.alloc_inner()
+///     }
+///
+///     unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+///         let index = slots.random_below(slots.entries().len());
+///         let old = slots.entries()[index].swap(ptr, Ordering::SeqCst);
+///         if !old.is_null() {
+///             slots.dealloc_inner(old);
+///         }
+///     }
+/// }
+///
+/// static SHUFFLED_SYSTEM_ALLOC: ShufflingAllocator<System, 256, SplitMix64, ShuffleFrees> =
+///     ShufflingAllocator::new(&System);
+/// ```
+pub trait ShuffleStrategy: 'static {
+    /// Fill a newly created array's entries.
+    ///
+    /// By default, every entry is filled with a new object from the inner
+    /// allocator.
+    ///
+    /// # Safety
+    ///
+    /// Only called by `ShufflingAllocator`, before the array is shared.
+    unsafe fn fill<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) {
+        for entry in slots.entries() {
+            let p = slots.alloc_inner();
+            if p.is_null() {
+                handle_alloc_error(slots.layout());
+            }
+            entry.store(p, Ordering::SeqCst);
+        }
+    }
+
+    /// Allocate an object of the size class, or return null on failure.
+    ///
+    /// # Safety
+    ///
+    /// Only called by `ShufflingAllocator`, from its `GlobalAlloc`
+    /// implementation.
+    unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8;
+
+    /// Free `ptr`, an object of the size class that was returned by `alloc`.
+    ///
+    /// # Safety
+    ///
+    /// Only called by `ShufflingAllocator`, from its `GlobalAlloc`
+    /// implementation.
+    unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8);
+}
+
+/// A shuffling array, as seen by a [`ShuffleStrategy`].
+pub struct Slots<'a, A, R> {
+    entries: &'a [AtomicPtr<u8>],
+    cursor: &'a AtomicUsize,
+    layout: Layout,
+    stats: ClassRef,
+    timer: TimerRef,
+    inner: &'a A,
+    rng: &'a R,
+}
+
+impl<'a, A, R> Slots<'a, A, R>
+where
+    A: GlobalAlloc,
+    R: ShuffleRng,
+{
+    pub(crate) fn new(
+        entries: &'a [AtomicPtr<u8>],
+        cursor: &'a AtomicUsize,
+        layout: Layout,
+        stats: ClassRef,
+        timer: TimerRef,
+        inner: &'a A,
+        rng: &'a R,
+    ) -> Self {
+        Slots {
+            entries,
+            cursor,
+            layout,
+            stats,
+            timer,
+            inner,
+            rng,
+        }
+    }
+
+    /// The array's entries, each of which is either empty (null) or holds an
+    /// object that isn't currently allocated.
+    ///
+    /// This is only the array's randomization window, so it may be shorter
+    /// than the allocator's `N`.
+    #[inline]
+    pub fn entries(&self) -> &'a [AtomicPtr<u8>] {
+        self.entries
+    }
+
+    /// A counter for the strategy to use however it likes. It starts at zero.
+    #[inline]
+    pub fn cursor(&self) -> &'a AtomicUsize {
+        self.cursor
+    }
+
+    /// The layout of the size class's objects.
+    #[inline]
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Allocate a new object from the inner allocator.
+    ///
+    /// # Safety
+    ///
+    /// Same as `GlobalAlloc::alloc`.
+    #[inline]
+    pub unsafe fn alloc_inner(&self) -> *mut u8 {
+        let p = self
+            .timer
+            .time(Part::Inner, || self.inner.alloc(self.layout));
+        if !p.is_null() {
+            self.stats.parked(1);
+        }
+        p
+    }
+
+    /// Return an object to the inner allocator.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by `alloc_inner`, and must not be in the
+    /// array's entries or in use.
+    #[inline]
+    pub unsafe fn dealloc_inner(&self, ptr: *mut u8) {
+        self.stats.parked(-1);
+        self.timer
+            .time(Part::Inner, || self.inner.dealloc(ptr, self.layout))
+    }
+
+    /// Get a random number in `0..n` from the array's random number stream.
+    #[inline]
+    pub fn random_below(&self, n: usize) -> usize {
+        self.stats.decided();
+        self.timer.time(Part::Shuffle, || {
+            scoped::seeded_below(n).unwrap_or_else(|| self.rng.below(n))
+        })
+    }
+
+    /// Put `ptr` in the entry at `index`, returning whatever was displaced to
+    /// the inner allocator.
+    #[inline]
+    unsafe fn replace(&self, index: usize, ptr: *mut u8) {
+        let old = self.entries[index].swap(ptr, Ordering::SeqCst);
+        if !old.is_null() {
+            self.dealloc_inner(old);
+        }
+    }
+}
+
+/// Take a random entry out of the array, replacing it with a new object from
+/// the inner allocator.
+#[inline]
+unsafe fn swap_out<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+    let replacement = slots.alloc_inner();
+    if replacement.is_null() {
+        return ptr::null_mut();
+    }
+    let index = slots.random_below(slots.entries.len());
+    slots.entries[index].swap(replacement, Ordering::SeqCst)
+}
+
+/// Put a freed object in a random entry of the array, returning the entry's
+/// old object to the inner allocator.
+#[inline]
+unsafe fn swap_in<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+    let index = slots.random_below(slots.entries.len());
+    slots.replace(index, ptr);
+}
+
+/// Swap a random entry out of the array on every allocation, and swap freed
+/// objects into a random entry on every free.
+///
+/// This is the algorithm from Stabilizer, and the default.
+pub struct SwapOnAllocAndFree;
+
+impl ShuffleStrategy for SwapOnAllocAndFree {
+    #[inline]
+    unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+        swap_out(slots)
+    }
+
+    #[inline]
+    unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+        swap_in(slots, ptr)
+    }
+}
+
+/// Swap a random entry out of the array on every allocation, but return freed
+/// objects straight to the inner allocator.
+///
+/// This leaves the inner allocator's reuse of freed memory alone, and only
+/// randomizes which of its objects are handed out.
+pub struct SwapOnAlloc;
+
+impl ShuffleStrategy for SwapOnAlloc {
+    #[inline]
+    unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+        swap_out(slots)
+    }
+
+    #[inline]
+    unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+        slots.dealloc_inner(ptr)
+    }
+}
+
+/// Allocate straight from the inner allocator, but swap freed objects into a
+/// random entry of the array on every free.
+///
+/// This leaves the inner allocator's placement of new objects alone, and only
+/// randomizes the order in which freed memory is returned to it.
+pub struct SwapOnFree;
+
+impl ShuffleStrategy for SwapOnFree {
+    #[inline]
+    unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+        slots.alloc_inner()
+    }
+
+    #[inline]
+    unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+        swap_in(slots, ptr)
+    }
+}
+
+/// Fill the whole array with new objects at once, in a random order, and hand
+/// them out one after another until the batch runs out.
+///
+/// Each batch is shuffled with Fisher–Yates, so it is a uniformly random
+/// permutation, and allocating only costs taking the next entry, but the
+/// array has to be refilled every `N` allocations. Freed objects are returned
+/// straight to the inner allocator, so they are reused in a random order as
+/// part of a later batch. While one thread is refilling the array, others
+/// allocate straight from the inner allocator.
+pub struct BatchRefill;
+
+impl BatchRefill {
+    /// Refill every entry with a new object, shuffling the entries with the
+    /// "inside-out" Fisher–Yates algorithm as they are filled in.
+    unsafe fn refill<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) {
+        for i in 0..slots.entries.len() {
+            let p = slots.alloc_inner();
+            let j = slots.random_below(i + 1);
+            let p = if j == i {
+                p
+            } else {
+                slots.entries[j].swap(p, Ordering::SeqCst)
+            };
+            slots.replace(i, p);
+        }
+    }
+}
+
+impl ShuffleStrategy for BatchRefill {
+    unsafe fn fill<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) {
+        Self::refill(slots);
+    }
+
+    #[inline]
+    unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+        let len = slots.entries.len();
+        loop {
+            let i = slots.cursor.fetch_add(1, Ordering::SeqCst);
+            if i < len {
+                let p = slots.entries[i].swap(ptr::null_mut(), Ordering::SeqCst);
+                return if p.is_null() { slots.alloc_inner() } else { p };
+            }
+            if i > len {
+                return slots.alloc_inner();
+            }
+
+            // This allocation exhausted the batch, so it is the one to refill
+            // the array before starting the next batch.
+            Self::refill(slots);
+            slots.cursor.store(0, Ordering::SeqCst);
+        }
+    }
+
+    #[inline]
+    unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+        slots.dealloc_inner(ptr)
+    }
+}
+
+/// Allocate straight from the inner allocator, but hold every freed object in
+/// a first-in, first-out queue, and only return it to the inner allocator once
+/// as many more objects have been freed as the array has entries.
+///
+/// This doesn't randomize anything by itself, but it keeps freed memory from
+/// being reused right away, which otherwise hides some of the inner
+/// allocator's locality effects.
+pub struct DelayedReuse;
+
+impl ShuffleStrategy for DelayedReuse {
+    unsafe fn fill<A: GlobalAlloc, R: ShuffleRng>(_slots: &Slots<'_, A, R>) {
+        // The queue starts out empty.
+    }
+
+    #[inline]
+    unsafe fn alloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>) -> *mut u8 {
+        slots.alloc_inner()
+    }
+
+    #[inline]
+    unsafe fn dealloc<A: GlobalAlloc, R: ShuffleRng>(slots: &Slots<'_, A, R>, ptr: *mut u8) {
+        let i = slots.cursor.fetch_add(1, Ordering::SeqCst) % slots.entries.len();
+        slots.replace(i, ptr);
+    }
+}
@@ -0,0 +1,99 @@
+//! Per-thread shuffling arrays.
+//!
+//! Thread-local storage can't be generic, so each thread holds a type-erased
+//! pointer to its arrays, along with the ID of the allocator that owns them and
+//! a function to free them when the thread exits. A thread keeps arrays for up
+//! to `MAX_ALLOCATORS` allocators, including ones that have since been dropped;
+//! any others used on the same thread fall back to their shared arrays.
+//!
+//! Allocators are identified by IDs rather than their addresses, because an
+//! allocator that isn't a `static` may be dropped and another one built at the
+//! same address, which mustn't inherit the first one's arrays.
+
+use std::{
+    cell::Cell,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The number of allocators that each thread can keep its own arrays for.
+const MAX_ALLOCATORS: usize = 4;
+
+/// A thread's arrays for one allocator, and how to free them.
+struct ThreadArrays {
+    /// The ID of the allocator that owns the arrays, or zero if the slot is
+    /// empty.
+    owner: Cell<u64>,
+    arrays: Cell<*mut ()>,
+    free: Cell<Option<unsafe fn(*mut ())>>,
+}
+
+impl Drop for ThreadArrays {
+    fn drop(&mut self) {
+        if let Some(free) = self.free.take() {
+            unsafe {
+                free(self.arrays.replace(ptr::null_mut()));
+            }
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: ThreadArrays = ThreadArrays {
+    owner: Cell::new(0),
+    arrays: Cell::new(ptr::null_mut()),
+    free: Cell::new(None),
+};
+
+thread_local! {
+    /// Set while this thread is accessing `ARRAYS`. Registering `ARRAYS`'s
+    /// destructor, or creating the arrays, may allocate, and those nested
+    /// allocations must not try to access `ARRAYS` again.
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+
+    static ARRAYS: [ThreadArrays; MAX_ALLOCATORS] = const {
+        [EMPTY; MAX_ALLOCATORS]
+    };
+}
+
+/// Get a new, unique ID for an allocator to own arrays with.
+pub(crate) fn new_owner() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Get this thread's arrays for `owner`, creating them with `create` if this
+/// thread doesn't have arrays for `owner` yet. `create` returns the arrays and a
+/// function that frees them, which is called when the thread exits.
+///
+/// Returns `None` if the arrays can't be used right now: while they are being
+/// created, after they have been freed during thread exit, or if this thread
+/// already has arrays for `MAX_ALLOCATORS` other allocators.
+#[inline]
+pub(crate) fn get(
+    owner: u64,
+    create: impl FnOnce() -> (*mut (), unsafe fn(*mut ())),
+) -> Option<*mut ()> {
+    if BUSY.try_with(|busy| busy.replace(true)).unwrap_or(true) {
+        return None;
+    }
+
+    let arrays = ARRAYS
+        .try_with(|slots| {
+            let slot = slots
+                .iter()
+                .find(|slot| slot.owner.get() == owner || slot.owner.get() == 0)?;
+            if slot.owner.get() == 0 {
+                let (arrays, free) = create();
+                slot.owner.set(owner);
+                slot.arrays.set(arrays);
+                slot.free.set(Some(free));
+            }
+            slot.free.get().map(|_| slot.arrays.get())
+        })
+        .ok()
+        .flatten();
+
+    BUSY.with(|busy| busy.set(false));
+    arrays
+}
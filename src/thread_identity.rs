@@ -0,0 +1,85 @@
+//! Identifying threads, so that each thread's random number streams can be the
+//! same from one run of a program to the next.
+//!
+//! A thread is identified by its name and by how many threads with the same
+//! name came before it. Threads that share a name, including unnamed threads,
+//! are therefore numbered in the order that they ask for their identity, and
+//! only threads with distinct names are identified the same way every time.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The number of distinct thread names that are counted separately. Threads
+/// with names beyond this are all counted together.
+const MAX_NAMES: usize = 64;
+
+/// How many threads with each name have been identified.
+pub(crate) struct ThreadNames {
+    /// Hashes of thread names, or zero for unused entries.
+    hashes: [AtomicU64; MAX_NAMES],
+    counts: [AtomicU64; MAX_NAMES],
+    overflow: AtomicU64,
+}
+
+impl ThreadNames {
+    pub fn new() -> Self {
+        ThreadNames {
+            hashes: [const { AtomicU64::new(0) }; MAX_NAMES],
+            counts: [const { AtomicU64::new(0) }; MAX_NAMES],
+            overflow: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the current thread's identity.
+    ///
+    /// This counts the current thread as another thread with its name, so it
+    /// should only be called once per thread.
+    pub fn identify(&self) -> u64 {
+        let mut buf = [0; 64];
+        // Zero marks unused entries, so make sure no name hashes to it.
+        let hash = fnv1a(current_name(&mut buf)) | 1;
+
+        let start = hash as usize % MAX_NAMES;
+        let count = (0..MAX_NAMES)
+            .map(|i| (start + i) % MAX_NAMES)
+            .find(|&i| {
+                let entry = &self.hashes[i];
+                match entry.compare_exchange(0, hash, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => true,
+                    Err(existing) => existing == hash,
+                }
+            })
+            .map_or(&self.overflow, |i| &self.counts[i]);
+
+        let occurrence = count.fetch_add(1, Ordering::SeqCst);
+        hash ^ occurrence.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+}
+
+/// The 64-bit FNV-1a hash.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))] {
+        /// Get the current thread's name, without allocating.
+        fn current_name(buf: &mut [u8; 64]) -> &[u8] {
+            let ret = unsafe {
+                libc::pthread_getname_np(libc::pthread_self(), buf.as_mut_ptr().cast(), buf.len())
+            };
+            if ret != 0 {
+                return &[];
+            }
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            &buf[..len]
+        }
+    } else {
+        /// Thread names aren't available on this platform, so every thread is
+        /// unnamed.
+        fn current_name(_buf: &mut [u8; 64]) -> &[u8] {
+            &[]
+        }
+    }
+}
@@ -0,0 +1,66 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+static BUMP: Bump = Bump::new();
+static SHUFFLED: ShufflingAllocator<Bump> = ShufflingAllocator::builder(&BUMP).seed(0).build();
+
+fn check_alignment<T>(make: impl Fn() -> T) {
+    let align = std::mem::align_of::<T>();
+    let boxes = (0..1024).map(|_| Box::new(make())).collect::<Vec<_>>();
+    for b in &boxes {
+        let p: *const T = &**b;
+        assert_eq!(
+            p as usize % align,
+            0,
+            "{:p} should be aligned to {}",
+            p,
+            align
+        );
+    }
+}
+
+#[test]
+fn aligned_allocations() {
+    #[allow(dead_code)]
+    #[repr(align(16))]
+    struct Align16(u8);
+    #[allow(dead_code)]
+    #[repr(align(64))]
+    struct Align64(u8);
+    #[allow(dead_code)]
+    #[repr(align(128))]
+    struct Align128(u8);
+
+    check_alignment(|| 0_u128);
+    check_alignment(|| Align16(1));
+    check_alignment(|| Align64(1));
+    check_alignment(|| Align128(1));
+}
+
+#[test]
+fn aligned_allocations_are_shuffled() {
+    for &align in &[16, 32, 64] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        let ptrs = (0..64)
+            .map(|_| unsafe { SHUFFLED.alloc(layout) })
+            .collect::<Vec<_>>();
+        assert!(ptrs.iter().all(|&p| p as usize % align == 0));
+
+        let offsets = ptrs.iter().map(|&p| BUMP.offset_of(p)).collect::<Vec<_>>();
+        assert!(
+            offsets.windows(2).any(|w| w[1] < w[0]),
+            "{}-aligned allocations should not come back in address order",
+            align
+        );
+
+        for p in ptrs {
+            unsafe { SHUFFLED.dealloc(p, layout) };
+        }
+    }
+}
@@ -0,0 +1,31 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::mem;
+
+#[global_allocator]
+static A: ShufflingAllocator<System, 16> = shuffling_allocator::wrap!(&System);
+
+static BUMP: Bump = Bump::new();
+static ONE: ShufflingAllocator<Bump, 1> = shuffling_allocator::wrap!(&BUMP);
+
+#[test]
+fn small_array() {
+    let boxes = (0..1024).map(Box::new).collect::<Vec<_>>();
+    drop(boxes);
+}
+
+#[test]
+fn single_entry_array() {
+    // Each allocation returns the pointer that the previous allocation put into
+    // the array, so the inner allocator's order shines through.
+    let layout = Layout::new::<usize>();
+    let offsets = (0..16)
+        .map(|_| unsafe { BUMP.offset_of(ONE.alloc(layout)) })
+        .collect::<Vec<_>>();
+    assert!(offsets
+        .windows(2)
+        .all(|w| w[1] - w[0] == mem::size_of::<usize>()));
+}
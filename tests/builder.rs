@@ -0,0 +1,46 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::mem;
+
+#[global_allocator]
+static A: ShufflingAllocator<System, 64> = ShufflingAllocator::builder(&System)
+    .seed(7)
+    .array_size(32)
+    .build();
+
+static BUMP_1: Bump = Bump::new();
+static BUMP_2: Bump = Bump::new();
+
+static SINGLE: ShufflingAllocator<Bump> =
+    ShufflingAllocator::builder(&BUMP_1).array_size(1).build();
+static DISABLED: ShufflingAllocator<Bump> =
+    ShufflingAllocator::builder(&BUMP_2).enabled(false).build();
+
+fn strides(alloc: &ShufflingAllocator<Bump>, bump: &Bump) -> Vec<usize> {
+    let layout = Layout::from_size_align(4, 4).unwrap();
+    let offsets = (0..16)
+        .map(|_| unsafe { bump.offset_of(alloc.alloc(layout)) })
+        .collect::<Vec<_>>();
+    offsets.windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+#[test]
+fn global() {
+    assert_eq!(A.seed(), 7);
+    let boxes = (0..1024).map(Box::new).collect::<Vec<_>>();
+    drop(boxes);
+}
+
+#[test]
+fn array_size() {
+    let size_class = mem::size_of::<usize>();
+    assert!(strides(&SINGLE, &BUMP_1).iter().all(|&s| s == size_class));
+}
+
+#[test]
+fn disabled() {
+    assert!(strides(&DISABLED, &BUMP_2).iter().all(|&s| s == 4));
+}
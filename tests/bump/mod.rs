@@ -0,0 +1,98 @@
+// Not every test uses every helper.
+#![allow(dead_code, unused_macros)]
+
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::UnsafeCell;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A bump allocator over a fixed buffer, so that the addresses handed out are
+/// a deterministic function of the allocation sequence.
+#[repr(C, align(4096))]
+pub struct Bump {
+    buf: UnsafeCell<[u8; 1 << 20]>,
+    next: AtomicUsize,
+}
+
+unsafe impl Sync for Bump {}
+
+impl Bump {
+    pub const fn new() -> Self {
+        Bump {
+            buf: UnsafeCell::new([0; 1 << 20]),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn offset_of(&self, p: *mut u8) -> usize {
+        p as usize - self.buf.get() as usize
+    }
+}
+
+unsafe impl GlobalAlloc for Bump {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = self.buf.get() as usize;
+        let mut next = self.next.load(Ordering::SeqCst);
+        loop {
+            let start = (base + next + layout.align() - 1) & !(layout.align() - 1);
+            let end = start + layout.size() - base;
+            if end > 1 << 20 {
+                return std::ptr::null_mut();
+            }
+            match self
+                .next
+                .compare_exchange(next, end, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return start as *mut u8,
+                Err(n) => next = n,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+/// A seeded shuffling allocator over its own bump allocator. Define these with
+/// `seeded!`.
+pub struct Seeded {
+    pub bump: &'static Bump,
+    pub alloc: ShufflingAllocator<Bump>,
+}
+
+impl Seeded {
+    /// Allocate 64 `u64`s, and get their offsets into the bump allocator.
+    pub fn layout(&self) -> Vec<usize> {
+        let layout = Layout::new::<u64>();
+        (0..64)
+            .map(|_| unsafe { self.bump.offset_of(self.alloc.alloc(layout)) })
+            .collect()
+    }
+}
+
+impl Deref for Seeded {
+    type Target = ShufflingAllocator<Bump>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.alloc
+    }
+}
+
+/// Define `static` `Seeded` allocators with the given seeds, each over its
+/// own bump allocator: `seeded! { SEEDED_1 = 1, SEEDED_2 = 2 }`.
+macro_rules! seeded {
+    ($($name:ident = $seed:expr),* $(,)?) => {
+        $(
+            static $name: $crate::bump::Seeded = {
+                static BUMP: $crate::bump::Bump = $crate::bump::Bump::new();
+                $crate::bump::Seeded {
+                    bump: &BUMP,
+                    alloc: shuffling_allocator::wrap!(&BUMP, seed = $seed),
+                }
+            };
+        )*
+    };
+}
+
+#[allow(unused_imports)]
+pub(crate) use seeded;
@@ -0,0 +1,44 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+
+static BUMP_1: Bump = Bump::new();
+static BUMP_2: Bump = Bump::new();
+
+static CONFIGURED: ShufflingAllocator<Bump> = shuffling_allocator::wrap!(&BUMP_1, seed = 1);
+static DISABLED: ShufflingAllocator<Bump> = shuffling_allocator::wrap!(&BUMP_2);
+
+fn strides(alloc: &ShufflingAllocator<Bump>, bump: &Bump) -> Vec<usize> {
+    let layout = Layout::from_size_align(4, 4).unwrap();
+    let offsets = (0..16)
+        .map(|_| unsafe { bump.offset_of(alloc.alloc(layout)) })
+        .collect::<Vec<_>>();
+    offsets.windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+// The environment is shared by the whole process, so this is all one test.
+#[test]
+fn environment_variables() {
+    env::set_var("SHUFFLING_ALLOCATOR_SEED", "0x2a");
+    env::set_var("SHUFFLING_ALLOCATOR_ARRAY_SIZE", "1");
+    env::remove_var("SHUFFLING_ALLOCATOR_DISABLE");
+
+    // The environment's seed takes precedence over the one given to `wrap!`.
+    assert_eq!(CONFIGURED.seed(), 42);
+
+    // With a single-entry shuffling array, each allocation returns the entry
+    // that was put into the array by the previous one, rounded up to the
+    // 8-byte size class.
+    let size_class = std::mem::size_of::<usize>();
+    assert!(strides(&CONFIGURED, &BUMP_1)
+        .iter()
+        .all(|&s| s == size_class));
+
+    // When disabled, allocations go straight through to the inner allocator
+    // without being rounded up to a size class.
+    env::set_var("SHUFFLING_ALLOCATOR_DISABLE", "1");
+    assert!(strides(&DISABLED, &BUMP_2).iter().all(|&s| s == 4));
+}
@@ -21,7 +21,7 @@ fn map() {
 
 #[test]
 fn strings() {
-    format!("foo, bar, {}", "baz");
+    let _ = format!("foo, bar, {}", "baz");
 }
 
 #[test]
@@ -54,6 +54,6 @@ fn test_larger_than_word_alignment() {
 
 #[test]
 fn many_small_allocs() {
-    let boxes = (0..1024).map(|i| Box::new(i)).collect::<Vec<_>>();
+    let boxes = (0..1024).map(Box::new).collect::<Vec<_>>();
     drop(boxes);
 }
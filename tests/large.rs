@@ -0,0 +1,77 @@
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashSet;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+    .large_object_pages(16)
+    .build();
+
+/// Wraps the system allocator and remembers the last block it allocated.
+struct Recording {
+    last: AtomicPtr<u8>,
+}
+
+unsafe impl GlobalAlloc for Recording {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        self.last.store(p, Ordering::SeqCst);
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+static RECORDING: Recording = Recording {
+    last: AtomicPtr::new(ptr::null_mut()),
+};
+static LARGE: ShufflingAllocator<Recording> = ShufflingAllocator::builder(&RECORDING)
+    .seed(0)
+    .large_object_pages(16)
+    .build();
+
+#[test]
+fn large_allocations() {
+    let mut vecs = Vec::new();
+    for i in 0..64 {
+        let mut v = vec![i as u8; 20_000 + i * 1000];
+        v.push(i as u8);
+        vecs.push(v);
+    }
+    for (i, v) in vecs.iter().enumerate() {
+        assert!(v.iter().all(|&x| x == i as u8));
+    }
+}
+
+#[test]
+fn large_allocations_are_placed_randomly() {
+    let layout = Layout::from_size_align(100_000, 64).unwrap();
+    let mut offsets = HashSet::new();
+    for _ in 0..32 {
+        unsafe {
+            let p = LARGE.alloc(layout);
+            let block = RECORDING.last.load(Ordering::SeqCst);
+            let offset = p as usize - block as usize;
+            assert_eq!(offset % 4096, 0);
+            assert!(offset <= 16 * 4096);
+            assert_eq!(p as usize % 64, 0);
+
+            p.write_bytes(0xAA, layout.size());
+            offsets.insert(offset);
+            LARGE.dealloc(p, layout);
+        }
+    }
+    assert!(offsets.len() > 1);
+
+    // Allocations aligned to more than a page are passed through.
+    let layout = Layout::from_size_align(100_000, 8192).unwrap();
+    unsafe {
+        let p = LARGE.alloc(layout);
+        assert_eq!(p, RECORDING.last.load(Ordering::SeqCst));
+        LARGE.dealloc(p, layout);
+    }
+}
@@ -0,0 +1,59 @@
+use shuffling_allocator::{LatencyHistogram, ShufflingAllocator};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+#[test]
+fn every_call_is_timed() {
+    static TIMED: ShufflingAllocator<System, 16> = ShufflingAllocator::builder(&System)
+        .large_object_pages(4)
+        .build();
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let large = Layout::from_size_align(1 << 16, 8).unwrap();
+    unsafe {
+        let ptrs: Vec<_> = (0..10).map(|_| TIMED.alloc(small)).collect();
+        let p = TIMED.alloc_zeroed(large);
+        TIMED.dealloc(p, large);
+        for p in ptrs {
+            TIMED.dealloc(p, small);
+        }
+    }
+
+    let latency = TIMED.latency();
+    assert_eq!(latency.alloc.total.count(), 11);
+    assert_eq!(latency.dealloc.total.count(), 11);
+    // Each shuffled allocation and free picks one random index, the large
+    // allocation picks its placement, and the first allocation fills the array.
+    assert_eq!(latency.alloc.shuffle.count(), 10 + 1);
+    assert_eq!(latency.alloc.inner.count(), 16 + 10 + 1);
+    assert_eq!(latency.dealloc.shuffle.count(), 10);
+    assert_eq!(latency.dealloc.inner.count(), 10 + 1);
+}
+
+#[test]
+fn histogram_buckets() {
+    assert_eq!(LatencyHistogram::bucket_range(0), 0..1);
+    assert_eq!(LatencyHistogram::bucket_range(1), 1..2);
+    assert_eq!(LatencyHistogram::bucket_range(10), 512..1024);
+    assert_eq!(LatencyHistogram::bucket_range(31).end, u64::MAX);
+
+    static TIMED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    let latency = TIMED.latency();
+    assert_eq!(latency.alloc.total.quantile(0.5), None);
+    unsafe {
+        let layout = Layout::new::<u64>();
+        TIMED.dealloc(TIMED.alloc(layout), layout);
+    }
+    let total = TIMED.latency().alloc.total;
+    let i = total.buckets().iter().position(|&n| n == 1).unwrap();
+    assert_eq!(
+        total.quantile(0.0),
+        Some(LatencyHistogram::bucket_range(i).end)
+    );
+    assert_eq!(
+        total.quantile(1.0),
+        Some(LatencyHistogram::bucket_range(i).end)
+    );
+}
@@ -0,0 +1,36 @@
+use shuffling_allocator::ShufflingAllocator;
+use std::{alloc::System, thread};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+    .per_cpu_arrays(true)
+    .build();
+
+#[test]
+fn concurrent_allocation() {
+    let threads: Vec<_> = (0..16)
+        .map(|t| {
+            thread::spawn(move || {
+                let mut boxes = Vec::new();
+                for i in 0..10_000usize {
+                    boxes.push(Box::new([t, i]));
+                    if i % 3 == 0 {
+                        boxes.swap_remove(i % boxes.len());
+                    }
+                    // Give the scheduler chances to migrate us.
+                    if i % 1000 == 0 {
+                        thread::yield_now();
+                    }
+                }
+                boxes
+            })
+        })
+        .collect();
+
+    // Free every thread's objects on this thread, likely on another CPU.
+    for (t, thread) in threads.into_iter().enumerate() {
+        for b in thread.join().unwrap() {
+            assert_eq!(b[0], t);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::{ClassPolicy, ShufflingAllocator, SizeClassTable};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+static COARSE: SizeClassTable = SizeClassTable::new(&[32, 1024]);
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+    .size_class_policy(0..=64, ClassPolicy::PassThrough)
+    .size_class_policy(128..=usize::MAX, ClassPolicy::Window(8))
+    .build();
+
+static BUMP_1: Bump = Bump::new();
+static PASS_SMALL: ShufflingAllocator<Bump> = ShufflingAllocator::builder(&BUMP_1)
+    .size_classes(&COARSE)
+    .size_class_policy(0..=usize::MAX, ClassPolicy::Shuffle)
+    .size_class_policy(0..=32, ClassPolicy::PassThrough)
+    .build();
+
+static BUMP_2: Bump = Bump::new();
+static NARROW_LARGE: ShufflingAllocator<Bump> = ShufflingAllocator::builder(&BUMP_2)
+    .size_classes(&COARSE)
+    .size_class_policy(1024..=1024, ClassPolicy::Window(1))
+    .build();
+
+fn offsets(alloc: &ShufflingAllocator<Bump>, bump: &Bump, layout: Layout) -> Vec<usize> {
+    (0..16)
+        .map(|_| unsafe { bump.offset_of(alloc.alloc(layout)) })
+        .collect()
+}
+
+#[test]
+fn boxes_under_mixed_policies() {
+    let boxes = (0..1024)
+        .map(|i| vec![i as u8; i % 512])
+        .collect::<Vec<_>>();
+    drop(boxes);
+}
+
+#[test]
+fn pass_through_class() {
+    // Allocations in the pass-through class come straight from the bump
+    // allocator, one after another and at their own size.
+    let small = offsets(
+        &PASS_SMALL,
+        &BUMP_1,
+        Layout::from_size_align(24, 8).unwrap(),
+    );
+    assert!(small.windows(2).all(|w| w[1] - w[0] == 24));
+
+    // The other class is still shuffled.
+    let large = offsets(
+        &PASS_SMALL,
+        &BUMP_1,
+        Layout::from_size_align(1000, 8).unwrap(),
+    );
+    assert!(large.windows(2).any(|w| w[1] < w[0]));
+}
+
+#[test]
+fn windowed_class() {
+    // With a one-entry window, each allocation returns the pointer that the
+    // previous allocation put into the array, so the bump allocator's order
+    // shines through.
+    let large = offsets(
+        &NARROW_LARGE,
+        &BUMP_2,
+        Layout::from_size_align(1000, 8).unwrap(),
+    );
+    assert!(large.windows(2).all(|w| w[1] - w[0] == 1024));
+
+    // The other class still uses the whole array.
+    let small = offsets(
+        &NARROW_LARGE,
+        &BUMP_2,
+        Layout::from_size_align(24, 8).unwrap(),
+    );
+    assert!(small.windows(2).any(|w| w[1] < w[0]));
+}
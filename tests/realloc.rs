@@ -0,0 +1,126 @@
+use shuffling_allocator::{ShufflingAllocator, SizeClassTable};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+    .large_object_pages(4)
+    .build();
+
+/// Wraps the system allocator and counts calls to `realloc`.
+struct Counting {
+    reallocs: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.reallocs.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+static COUNTING_1: Counting = Counting {
+    reallocs: AtomicUsize::new(0),
+};
+static COUNTING_2: Counting = Counting {
+    reallocs: AtomicUsize::new(0),
+};
+static COUNTING_3: Counting = Counting {
+    reallocs: AtomicUsize::new(0),
+};
+
+static PASS_THROUGH: ShufflingAllocator<Counting> = ShufflingAllocator::new(&COUNTING_1);
+static LARGE: ShufflingAllocator<Counting> = ShufflingAllocator::builder(&COUNTING_2)
+    .large_object_pages(4)
+    .build();
+static SMALL: ShufflingAllocator<Counting> = ShufflingAllocator::new(&COUNTING_3);
+
+unsafe fn fill(p: *mut u8, len: usize) {
+    for i in 0..len {
+        *p.add(i) = i as u8;
+    }
+}
+
+unsafe fn check(p: *mut u8, len: usize) {
+    for i in 0..len {
+        assert_eq!(*p.add(i), i as u8);
+    }
+}
+
+#[test]
+fn growing_vecs() {
+    let mut vecs = (0..100).map(|_| Vec::new()).collect::<Vec<_>>();
+    for i in 0..10_000 {
+        for v in &mut vecs {
+            v.push(i);
+        }
+    }
+    for v in &vecs {
+        assert!(v.iter().copied().eq(0..10_000));
+    }
+}
+
+#[test]
+fn pass_through_uses_inner_realloc() {
+    unsafe {
+        let layout = Layout::from_size_align(100_000, 8).unwrap();
+        let p = PASS_THROUGH.alloc(layout);
+        fill(p, layout.size());
+        let p = PASS_THROUGH.realloc(p, layout, 200_000);
+        assert_eq!(COUNTING_1.reallocs.load(Ordering::SeqCst), 1);
+        check(p, layout.size());
+        PASS_THROUGH.dealloc(p, Layout::from_size_align(200_000, 8).unwrap());
+    }
+}
+
+#[test]
+fn large_uses_inner_realloc() {
+    unsafe {
+        let layout = Layout::from_size_align(100_000, 16).unwrap();
+        let p = LARGE.alloc(layout);
+        fill(p, layout.size());
+
+        let p = LARGE.realloc(p, layout, 300_000);
+        check(p, layout.size());
+        fill(p, 300_000);
+
+        let p = LARGE.realloc(p, Layout::from_size_align(300_000, 16).unwrap(), 50_000);
+        check(p, 50_000);
+        assert_eq!(p as usize % 16, 0);
+
+        assert_eq!(COUNTING_2.reallocs.load(Ordering::SeqCst), 2);
+        LARGE.dealloc(p, Layout::from_size_align(50_000, 16).unwrap());
+    }
+}
+
+#[test]
+fn same_size_class_does_not_move() {
+    unsafe {
+        let size_class = SizeClassTable::DEFAULT.size_class(17).unwrap();
+        let layout = Layout::from_size_align(17, 1).unwrap();
+        let p = SMALL.alloc(layout);
+        fill(p, 17);
+
+        let q = SMALL.realloc(p, layout, size_class);
+        assert_eq!(p, q);
+        check(q, 17);
+
+        let r = SMALL.realloc(
+            q,
+            Layout::from_size_align(size_class, 1).unwrap(),
+            size_class + 1,
+        );
+        check(r, 17);
+        SMALL.dealloc(r, Layout::from_size_align(size_class + 1, 1).unwrap());
+
+        assert_eq!(COUNTING_3.reallocs.load(Ordering::SeqCst), 0);
+    }
+}
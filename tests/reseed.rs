@@ -0,0 +1,32 @@
+mod bump;
+
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::System;
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+bump::seeded! {
+    SEEDED_1 = 1,
+    SEEDED_2 = 2,
+    SEEDED_3 = 3,
+    SEEDED_4 = 4,
+}
+
+#[test]
+fn reseed() {
+    SEEDED_2.reseed(1);
+    assert_eq!(SEEDED_2.seed(), 1);
+    assert_eq!(SEEDED_1.layout(), SEEDED_2.layout());
+}
+
+#[test]
+fn snapshot_and_restore() {
+    let snapshot = SEEDED_3.rng_snapshot();
+    assert_eq!(snapshot.seed(), 3);
+    let layout_3 = SEEDED_3.layout();
+
+    SEEDED_4.restore_rng(&snapshot);
+    assert_eq!(SEEDED_4.seed(), 3);
+    assert_eq!(layout_3, SEEDED_4.layout());
+}
@@ -0,0 +1,110 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::{
+    Locked, ShuffleRng, ShufflingAllocator, SmallRng, SplitMix64, WyRand, Xorshift64,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[global_allocator]
+static A: ShufflingAllocator<System, 256, Xorshift64> = ShufflingAllocator::new(&System);
+
+/// Allocate a bunch of objects from a fresh allocator with the given
+/// generator, and return their offsets into its bump allocator.
+fn layout_with<R: ShuffleRng>(seed: u64) -> Vec<usize> {
+    static BUMPS: [Bump; 32] = [const { Bump::new() }; 32];
+    static NEXT_BUMP: AtomicUsize = AtomicUsize::new(0);
+    let bump = &BUMPS[NEXT_BUMP.fetch_add(1, Ordering::SeqCst)];
+    let alloc: &'static ShufflingAllocator<Bump, 256, R> = Box::leak(Box::new(
+        ShufflingAllocator::builder(bump).seed(seed).build(),
+    ));
+    let layout = Layout::new::<u64>();
+    (0..64)
+        .map(|_| unsafe { bump.offset_of(alloc.alloc(layout)) })
+        .collect()
+}
+
+fn check_rng<R: ShuffleRng>() {
+    let layout = layout_with::<R>(1);
+    assert_eq!(layout, layout_with::<R>(1));
+    assert_ne!(layout, layout_with::<R>(2));
+
+    let rng = R::from_seed(3);
+    for n in 1..100 {
+        assert!(rng.below(n) < n);
+    }
+
+    let saved = rng.save();
+    let outputs: Vec<_> = (0..10).map(|_| rng.next_u64()).collect();
+    rng.restore(&saved);
+    assert_eq!(outputs, (0..10).map(|_| rng.next_u64()).collect::<Vec<_>>());
+
+    rng.reseed(4);
+    let fresh = R::from_seed(4);
+    assert!((0..10).all(|_| rng.next_u64() == fresh.next_u64()));
+}
+
+#[test]
+fn built_in_rngs() {
+    check_rng::<SplitMix64>();
+    check_rng::<WyRand>();
+    check_rng::<Xorshift64>();
+    check_rng::<SmallRng>();
+    check_rng::<Locked<rand::rngs::StdRng>>();
+}
+
+/// Always picks the first entry of the shuffling array.
+struct First;
+
+impl ShuffleRng for First {
+    type State = ();
+
+    fn from_seed(_seed: u64) -> Self {
+        First
+    }
+
+    fn next_u64(&self) -> u64 {
+        0
+    }
+
+    fn save(&self) {}
+
+    fn restore(&self, _state: &()) {}
+}
+
+#[test]
+fn custom_rng() {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+
+    struct Counting(SplitMix64);
+
+    impl ShuffleRng for Counting {
+        type State = u64;
+
+        fn from_seed(seed: u64) -> Self {
+            Counting(SplitMix64::from_seed(seed))
+        }
+
+        fn next_u64(&self) -> u64 {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            self.0.next_u64()
+        }
+
+        fn save(&self) -> u64 {
+            self.0.save()
+        }
+
+        fn restore(&self, state: &u64) {
+            self.0.restore(state)
+        }
+    }
+
+    let layout = layout_with::<Counting>(1);
+    assert_eq!(CALLS.load(Ordering::SeqCst), layout.len() as u64);
+
+    // With the first entry always chosen, each allocation hands out the
+    // object that the previous one put into the array.
+    let layout = layout_with::<First>(1);
+    assert!(layout.windows(2).all(|w| w[0] < w[1]));
+}
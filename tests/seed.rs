@@ -0,0 +1,35 @@
+mod bump;
+
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::System;
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = shuffling_allocator::wrap!(&System, seed = 0x5eed);
+
+bump::seeded! {
+    SEEDED_1 = 1,
+    SEEDED_2 = 1,
+    SEEDED_3 = 2,
+}
+
+#[test]
+fn seed_is_reported() {
+    assert_eq!(A.seed(), 0x5eed);
+    let _ = Box::new(1);
+    assert_eq!(A.seed(), 0x5eed);
+}
+
+#[test]
+fn entropy_seed_is_stable() {
+    static UNSEEDED: ShufflingAllocator<System> = shuffling_allocator::wrap!(&System);
+    assert_eq!(UNSEEDED.seed(), UNSEEDED.seed());
+}
+
+#[test]
+fn same_seed_same_layout() {
+    let layout_1 = SEEDED_1.layout();
+    let layout_2 = SEEDED_2.layout();
+    let layout_3 = SEEDED_3.layout();
+    assert_eq!(layout_1, layout_2);
+    assert_ne!(layout_1, layout_3);
+}
@@ -0,0 +1,60 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::{ShufflingAllocator, SizeClassTable};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+static COARSE: SizeClassTable = SizeClassTable::new(&[32, 1024]);
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+    .size_classes(&COARSE)
+    .build();
+
+static BUMP: Bump = Bump::new();
+static SINGLE: ShufflingAllocator<Bump> = ShufflingAllocator::builder(&BUMP)
+    .size_classes(&COARSE)
+    .array_size(1)
+    .build();
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn default_size_classes() {
+    assert_eq!(
+        SizeClassTable::DEFAULT.sizes(),
+        &[
+            8, 16, 24, 32, 40, 56, 72, 88, 104, 136, 168, 200, 232, 296, 360, 424, 488, 616, 744,
+            872, 1000, 1256, 1512, 1768, 2024, 2536, 3048, 3560, 4072, 5096, 6120, 7144
+        ]
+    );
+}
+
+#[test]
+fn lookup() {
+    let table = &SizeClassTable::DEFAULT;
+    assert_eq!(table.size_class(0), Some(table.sizes()[0]));
+    for window in table.sizes().windows(2) {
+        assert_eq!(table.size_class(window[0]), Some(window[0]));
+        assert_eq!(table.size_class(window[0] + 1), Some(window[1]));
+    }
+    let largest = *table.sizes().last().unwrap();
+    assert_eq!(table.size_class(largest + 1), None);
+
+    assert_eq!(COARSE.len(), 2);
+    assert_eq!(COARSE.size_class(33), Some(1024));
+    assert!(SizeClassTable::new(&[]).is_empty());
+    assert_eq!(SizeClassTable::new(&[]).size_class(1), None);
+}
+
+#[test]
+fn custom_size_classes() {
+    // Every allocation is rounded up to the 32-byte class.
+    let layout = Layout::from_size_align(17, 1).unwrap();
+    let offsets = (0..16)
+        .map(|_| unsafe { BUMP.offset_of(SINGLE.alloc(layout)) })
+        .collect::<Vec<_>>();
+    assert!(offsets.windows(2).all(|w| w[1] - w[0] == 32));
+
+    let boxes = (0..1024).map(|i| vec![i as u8; i]).collect::<Vec<_>>();
+    drop(boxes);
+}
@@ -0,0 +1,312 @@
+use rand::{RngCore, SeedableRng};
+use shuffling_allocator::{
+    ClassPolicy, Locked, ShuffleRng, ShufflingAllocator, SmallRng, StatsFormat,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use std::{env, fs};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// Hold this while changing phases. The current phase is process-wide, so
+/// tests that change it would otherwise count each other's operations in the
+/// wrong phases.
+fn phase_lock() -> MutexGuard<'static, ()> {
+    static PHASES: Mutex<()> = Mutex::new(());
+    PHASES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[test]
+fn class_counts() {
+    static COUNTED: ShufflingAllocator<System, 16> = ShufflingAllocator::new(&System);
+
+    unsafe {
+        let ptrs: Vec<_> = (0..10).map(|_| COUNTED.alloc(layout(24, 8))).collect();
+        for &p in &ptrs[..4] {
+            COUNTED.dealloc(p, layout(24, 8));
+        }
+
+        let stats = COUNTED.stats();
+        let class = stats.classes().iter().find(|c| c.size == 24).unwrap();
+        assert_eq!((class.allocs, class.frees), (10, 4));
+        // The array is always full.
+        assert_eq!(class.parked, 16);
+        assert_eq!(stats.parked_bytes(), 16 * 24);
+        assert!(stats
+            .classes()
+            .iter()
+            .filter(|c| c.size != 24)
+            .all(|c| c.allocs == 0 && c.parked == 0));
+
+        for &p in &ptrs[4..] {
+            COUNTED.dealloc(p, layout(24, 8));
+        }
+    }
+}
+
+#[test]
+fn bypass_counts() {
+    static COUNTED: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+        .size_class_policy(8..=8, ClassPolicy::PassThrough)
+        .build();
+
+    unsafe {
+        let huge = COUNTED.alloc(layout(1 << 20, 8));
+        let aligned = COUNTED.alloc(layout(64, 128));
+        let passed = COUNTED.alloc(layout(8, 8));
+        COUNTED.set_enabled(false);
+        let unshuffled = COUNTED.alloc(layout(64, 8));
+        COUNTED.set_enabled(true);
+
+        let stats = COUNTED.stats();
+        assert_eq!(stats.bypassed_size, 1);
+        assert_eq!(stats.bypassed_align, 1);
+        assert_eq!(stats.unshuffled, 2);
+        assert!(stats.classes().iter().all(|c| c.allocs == 0));
+
+        COUNTED.dealloc(huge, layout(1 << 20, 8));
+        COUNTED.dealloc(aligned, layout(64, 128));
+        COUNTED.dealloc(passed, layout(8, 8));
+        COUNTED.dealloc(unshuffled, layout(64, 8));
+    }
+}
+
+#[test]
+fn live_and_peak_bytes() {
+    static COUNTED: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+        .large_object_pages(4)
+        .build();
+
+    unsafe {
+        let small = COUNTED.alloc(layout(100, 8));
+        let large = COUNTED.alloc(layout(1 << 16, 8));
+        assert_eq!(COUNTED.stats().live_bytes, 100 + (1 << 16));
+
+        // Grow within the size class, and then out of it.
+        let small = COUNTED.realloc(small, layout(100, 8), 101);
+        assert_eq!(COUNTED.stats().live_bytes, 101 + (1 << 16));
+        let small = COUNTED.realloc(small, layout(101, 8), 1000);
+        assert_eq!(COUNTED.stats().live_bytes, 1000 + (1 << 16));
+
+        COUNTED.dealloc(large, layout(1 << 16, 8));
+        COUNTED.dealloc(small, layout(1000, 8));
+        let stats = COUNTED.stats();
+        assert_eq!(stats.live_bytes, 0);
+        // Moving out of the size class briefly needed both the old and new
+        // allocations.
+        assert_eq!(stats.peak_bytes, 101 + 1000 + (1 << 16));
+    }
+}
+
+#[test]
+fn rng_lock_contention() {
+    static LOCKED: ShufflingAllocator<System, 16, SmallRng> = ShufflingAllocator::new(&System);
+
+    // Lock-free generators never wait.
+    assert_eq!(A.stats().rng_lock.contended, 0);
+
+    // A single thread never finds the lock held.
+    let layout = layout(24, 8);
+    unsafe {
+        LOCKED.dealloc(LOCKED.alloc(layout), layout);
+    }
+    let rng_lock = LOCKED.stats().rng_lock;
+    assert_eq!((rng_lock.contended, rng_lock.wait_nanos), (0, 0));
+    assert_eq!(SmallRng::from_seed(0).contention().contended, 0);
+}
+
+/// A generator that, on its first use, holds its caller inside `next_u64` for
+/// a while.
+#[derive(Clone)]
+struct Slow(u64);
+
+static SLOW_ENTERED: AtomicBool = AtomicBool::new(false);
+
+impl RngCore for Slow {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if !SLOW_ENTERED.swap(true, Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        self.0 += 1;
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Slow {
+    type Seed = [u8; 8];
+
+    fn from_seed(seed: [u8; 8]) -> Self {
+        Slow(u64::from_le_bytes(seed))
+    }
+}
+
+#[test]
+fn rng_lock_contention_is_counted() {
+    let rng: &'static Locked<Slow> = Box::leak(Box::new(Locked::from_seed(0)));
+
+    // Hold the lock on one thread while another asks for a number.
+    let holder = thread::spawn(move || rng.next_u64());
+    while !SLOW_ENTERED.load(Ordering::SeqCst) {
+        thread::yield_now();
+    }
+    rng.next_u64();
+    holder.join().unwrap();
+
+    let contention = rng.contention();
+    assert_eq!(contention.contended, 1);
+    assert!(contention.wait_nanos > 0);
+}
+
+#[test]
+fn phase_counts() {
+    static COUNTED: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+        .size_class_policy(8..=8, ClassPolicy::PassThrough)
+        .build();
+
+    // Only this test's allocator is used in its phases, so the phases' counts
+    // are exact, as long as no other test changes phases meanwhile.
+    let _phases = phase_lock();
+    unsafe {
+        shuffling_allocator::phase("phase_counts setup");
+        let kept = COUNTED.alloc(layout(64, 8));
+        let passed = COUNTED.alloc(layout(8, 8));
+
+        shuffling_allocator::phase("phase_counts measure");
+        COUNTED.dealloc(passed, layout(8, 8));
+        let kept = COUNTED.realloc(kept, layout(64, 8), 60);
+        let huge = COUNTED.alloc(layout(1 << 20, 8));
+        COUNTED.dealloc(huge, layout(1 << 20, 8));
+
+        shuffling_allocator::phase("phase_counts setup");
+        COUNTED.dealloc(kept, layout(60, 8));
+        shuffling_allocator::phase("default");
+
+        let stats = COUNTED.stats();
+        let setup = stats.phase("phase_counts setup").unwrap();
+        assert_eq!((setup.allocs, setup.frees, setup.bypassed), (2, 1, 1));
+        assert_eq!((setup.bytes_allocated, setup.bytes_freed), (64 + 8, 60));
+        // Filling the array takes no decisions; shuffling the allocation
+        // and free of `kept` takes one each.
+        assert_eq!(setup.decisions, 2);
+
+        let measure = stats.phase("phase_counts measure").unwrap();
+        assert_eq!((measure.allocs, measure.frees, measure.bypassed), (1, 2, 1));
+        assert_eq!(
+            (measure.bytes_allocated, measure.bytes_freed),
+            (1 << 20, 8 + 4 + (1 << 20))
+        );
+        assert_eq!(measure.decisions, 0);
+
+        assert_eq!(stats.phases()[0].name, "default");
+    }
+}
+
+#[test]
+fn export_formats() {
+    static COUNTED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+    let stats = unsafe {
+        let _phases = phase_lock();
+        shuffling_allocator::phase("export \"quoted\"");
+        let p = COUNTED.alloc(layout(24, 8));
+        let stats = COUNTED.stats();
+        COUNTED.dealloc(p, layout(24, 8));
+        shuffling_allocator::phase("default");
+        stats
+    };
+    let write = |format| {
+        let mut out = Vec::new();
+        stats.write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let json = write(StatsFormat::Json);
+    assert!(json.starts_with("{\"bypassed_size\":0,"));
+    assert!(json.contains("\"live_bytes\":24,"));
+    assert!(json.contains("{\"size\":24,\"allocs\":1,\"frees\":0,\"parked\":256}"));
+    assert!(json.contains("{\"name\":\"export \\\"quoted\\\"\",\"allocs\":1,"));
+    assert!(json.ends_with("]}\n"));
+
+    let csv = write(StatsFormat::Csv);
+    let rows: Vec<_> = csv.lines().collect();
+    assert_eq!(rows[0], "kind,key,metric,value");
+    assert!(rows.contains(&"total,,live_bytes,24"));
+    assert!(rows.contains(&"class,24,allocs,1"));
+    assert!(rows.contains(&"phase,\"export \"\"quoted\"\"\",allocs,1"));
+    assert!(rows[1..]
+        .iter()
+        .all(|row| row.rsplit(',').next().unwrap().parse::<u64>().is_ok()));
+
+    let prometheus = write(StatsFormat::Prometheus);
+    assert!(prometheus.contains("# TYPE shuffling_allocator_live_bytes gauge\n"));
+    assert!(prometheus.contains("\nshuffling_allocator_live_bytes 24\n"));
+    assert!(prometheus.contains("# TYPE shuffling_allocator_class_allocs_total counter\n"));
+    assert!(prometheus.contains("\nshuffling_allocator_class_allocs_total{size=\"24\"} 1\n"));
+    assert!(prometheus
+        .contains("\nshuffling_allocator_phase_allocs_total{phase=\"export \\\"quoted\\\"\"} 1\n"));
+}
+
+#[test]
+fn dump_at_exit() {
+    static DUMPED: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+    const PATH_VAR: &str = "SHUFFLING_ALLOCATOR_TEST_DUMP_PATH";
+
+    // In the child process, register the dumps, allocate, and exit without
+    // returning to the test harness.
+    if let Ok(path) = env::var(PATH_VAR) {
+        DUMPED.dump_stats_at_exit(format!("{}.json", path), StatsFormat::Json);
+        DUMPED.dump_stats_at_exit(format!("{}.csv", path), StatsFormat::Csv);
+        unsafe {
+            DUMPED.alloc(layout(24, 8));
+        }
+        process::exit(0);
+    }
+
+    let path = env::temp_dir().join(format!("shuffling-allocator-dump-{}", process::id()));
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["dump_at_exit", "--exact", "--test-threads=1"])
+        .env(PATH_VAR, &path)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let read = |extension| {
+        let file = format!("{}.{}", path.display(), extension);
+        let contents = fs::read_to_string(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        contents
+    };
+    let json = read("json");
+    assert!(json.starts_with("{\"bypassed_size\":0,"));
+    assert!(json.contains("\"live_bytes\":24,"));
+    assert!(json.contains("{\"size\":24,\"allocs\":1,"));
+    assert!(json.ends_with("]}\n"));
+    let csv = read("csv");
+    assert!(csv.starts_with("kind,key,metric,value\n"));
+    assert!(csv.lines().any(|row| row == "class,24,allocs,1"));
+}
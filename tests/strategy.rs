@@ -0,0 +1,148 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::{
+    BatchRefill, DelayedReuse, ShuffleStrategy, ShufflingAllocator, SplitMix64, SwapOnAlloc,
+    SwapOnAllocAndFree, SwapOnFree,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[global_allocator]
+static A: ShufflingAllocator<System, 256, SplitMix64, BatchRefill> =
+    ShufflingAllocator::new(&System);
+
+/// Wraps the system allocator, remembering the last deallocation.
+struct Freeing {
+    last: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Freeing {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.last.store(ptr as usize, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(32, 8) };
+
+/// Allocate and free from many threads at once, checking that no object is
+/// handed out twice.
+fn churn<S: ShuffleStrategy>(alloc: &'static ShufflingAllocator<System, 16, SplitMix64, S>) {
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            thread::spawn(move || unsafe {
+                let mut live = Vec::new();
+                for i in 0..10_000usize {
+                    let p = alloc.alloc(LAYOUT).cast::<[usize; 4]>();
+                    p.write([t, i, t, i]);
+                    live.push(p);
+                    if i % 3 == 0 {
+                        let p = live.swap_remove(i % live.len());
+                        let [a, b, c, d] = p.read();
+                        assert_eq!((a, b), (c, d));
+                        assert_eq!(a, t);
+                        alloc.dealloc(p.cast(), LAYOUT);
+                    }
+                }
+                for p in live {
+                    assert_eq!(p.read()[0], t);
+                    alloc.dealloc(p.cast(), LAYOUT);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[test]
+fn strategies_under_contention() {
+    static BOTH: ShufflingAllocator<System, 16, SplitMix64, SwapOnAllocAndFree> =
+        ShufflingAllocator::new(&System);
+    static ALLOC: ShufflingAllocator<System, 16, SplitMix64, SwapOnAlloc> =
+        ShufflingAllocator::new(&System);
+    static FREE: ShufflingAllocator<System, 16, SplitMix64, SwapOnFree> =
+        ShufflingAllocator::new(&System);
+    static BATCH: ShufflingAllocator<System, 16, SplitMix64, BatchRefill> =
+        ShufflingAllocator::new(&System);
+    static DELAYED: ShufflingAllocator<System, 16, SplitMix64, DelayedReuse> =
+        ShufflingAllocator::new(&System);
+
+    churn(&BOTH);
+    churn(&ALLOC);
+    churn(&FREE);
+    churn(&BATCH);
+    churn(&DELAYED);
+}
+
+#[test]
+fn swap_on_free_allocates_in_order() {
+    static BUMP: Bump = Bump::new();
+    static FREE: ShufflingAllocator<Bump, 16, SplitMix64, SwapOnFree> =
+        ShufflingAllocator::new(&BUMP);
+
+    let offsets: Vec<_> = (0..16)
+        .map(|_| unsafe { BUMP.offset_of(FREE.alloc(LAYOUT)) })
+        .collect();
+    assert!(offsets.windows(2).all(|w| w[1] - w[0] == 32));
+}
+
+#[test]
+fn swap_on_alloc_frees_immediately() {
+    static FREEING: Freeing = Freeing {
+        last: AtomicUsize::new(0),
+    };
+    static ALLOC: ShufflingAllocator<Freeing, 16, SplitMix64, SwapOnAlloc> =
+        ShufflingAllocator::new(&FREEING);
+
+    unsafe {
+        let p = ALLOC.alloc(LAYOUT);
+        ALLOC.dealloc(p, LAYOUT);
+        assert_eq!(FREEING.last.load(Ordering::SeqCst), p as usize);
+    }
+}
+
+#[test]
+fn batches_are_permutations() {
+    static BUMP: Bump = Bump::new();
+    static BATCH: ShufflingAllocator<Bump, 16, SplitMix64, BatchRefill> =
+        ShufflingAllocator::new(&BUMP);
+
+    for _ in 0..4 {
+        let mut offsets: Vec<_> = (0..16)
+            .map(|_| unsafe { BUMP.offset_of(BATCH.alloc(LAYOUT)) })
+            .collect();
+        let shuffled = offsets.windows(2).any(|w| w[1] < w[0]);
+        offsets.sort_unstable();
+        assert!(offsets.windows(2).all(|w| w[1] - w[0] == 32));
+        assert!(shuffled);
+    }
+}
+
+#[test]
+fn delayed_reuse_is_first_in_first_out() {
+    static FREEING: Freeing = Freeing {
+        last: AtomicUsize::new(0),
+    };
+    static DELAYED: ShufflingAllocator<Freeing, 16, SplitMix64, DelayedReuse> =
+        ShufflingAllocator::new(&FREEING);
+
+    unsafe {
+        let ptrs: Vec<_> = (0..32).map(|_| DELAYED.alloc(LAYOUT)).collect();
+        for &p in &ptrs[..16] {
+            DELAYED.dealloc(p, LAYOUT);
+            assert_eq!(FREEING.last.load(Ordering::SeqCst), 0);
+        }
+        for (&p, &freed) in ptrs[16..].iter().zip(&ptrs) {
+            DELAYED.dealloc(p, LAYOUT);
+            assert_eq!(FREEING.last.load(Ordering::SeqCst), freed as usize);
+        }
+    }
+}
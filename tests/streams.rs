@@ -0,0 +1,57 @@
+mod bump;
+
+use bump::Bump;
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+/// Puts `u64`s in a bump allocator, so that their layout can be compared, and
+/// everything else in the system allocator.
+struct SplitBump(Bump);
+
+unsafe impl GlobalAlloc for SplitBump {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout == Layout::new::<u64>() {
+            self.0.alloc(layout)
+        } else {
+            System.alloc(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout != Layout::new::<u64>() {
+            System.dealloc(ptr, layout)
+        }
+    }
+}
+
+static SPLIT_1: SplitBump = SplitBump(Bump::new());
+static SPLIT_2: SplitBump = SplitBump(Bump::new());
+
+static SEEDED_1: ShufflingAllocator<SplitBump> = shuffling_allocator::wrap!(&SPLIT_1, seed = 7);
+static SEEDED_2: ShufflingAllocator<SplitBump> = shuffling_allocator::wrap!(&SPLIT_2, seed = 7);
+
+#[test]
+fn size_classes_shuffle_independently() {
+    let small = Layout::new::<u64>();
+    let other = Layout::new::<[u64; 3]>();
+
+    let mut layout_1 = vec![];
+    let mut layout_2 = vec![];
+    for i in 0..64 {
+        unsafe {
+            layout_1.push(SPLIT_1.0.offset_of(SEEDED_1.alloc(small)));
+
+            // Only the second allocator makes allocations in another size
+            // class in between.
+            for _ in 0..i % 5 {
+                let p = SEEDED_2.alloc(other);
+                SEEDED_2.dealloc(p, other);
+            }
+            layout_2.push(SPLIT_2.0.offset_of(SEEDED_2.alloc(small)));
+        }
+    }
+    assert_eq!(layout_1, layout_2);
+}
@@ -0,0 +1,199 @@
+use shuffling_allocator::{unshuffled, ShufflingAllocator};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::builder(&System)
+    .thread_local_arrays(true)
+    .build();
+
+/// Wraps the system allocator and counts live allocations.
+struct Counting {
+    live: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.live.fetch_sub(1, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+static COUNTING: Counting = Counting {
+    live: AtomicUsize::new(0),
+};
+
+static COUNTED: ShufflingAllocator<Counting, 16> = ShufflingAllocator::builder(&COUNTING)
+    .thread_local_arrays(true)
+    .build();
+
+#[test]
+fn arrays_are_freed_on_thread_exit() {
+    // Initialize the allocator's shared state up front.
+    COUNTED.seed();
+    let live = COUNTING.live.load(Ordering::SeqCst);
+
+    thread::spawn(move || unsafe {
+        let layout = Layout::new::<[u64; 4]>();
+        let ptrs: Vec<_> = (0..100).map(|_| COUNTED.alloc(layout)).collect();
+        for p in ptrs {
+            COUNTED.dealloc(p, layout);
+        }
+        // The thread's array is full of objects waiting to be handed out.
+        assert!(COUNTING.live.load(Ordering::SeqCst) > live);
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(COUNTING.live.load(Ordering::SeqCst), live);
+}
+
+#[test]
+fn objects_move_between_threads() {
+    let boxes: Vec<_> = (0..10_000usize).map(Box::new).collect();
+    let boxes = thread::spawn(move || {
+        for (i, b) in boxes.iter().enumerate() {
+            assert_eq!(**b, i);
+        }
+        let mut boxes = boxes;
+        boxes.extend((10_000..20_000).map(Box::new));
+        boxes
+    })
+    .join()
+    .unwrap();
+    for (i, b) in boxes.into_iter().enumerate() {
+        assert_eq!(*b, i);
+    }
+}
+
+thread_local! {
+    static LOG: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Wraps the system allocator and logs the addresses that it hands out on
+/// each thread.
+struct Logging;
+
+unsafe impl GlobalAlloc for Logging {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        LOG.with(|log| log.borrow_mut().push(p as usize));
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+static RUN_1: ShufflingAllocator<Logging, 16> = ShufflingAllocator::builder(&Logging)
+    .seed(3)
+    .thread_local_arrays(true)
+    .build();
+static RUN_2: ShufflingAllocator<Logging, 16> = ShufflingAllocator::builder(&Logging)
+    .seed(3)
+    .thread_local_arrays(true)
+    .build();
+
+/// Allocate on a few threads at once, and get each thread's layout: the order
+/// in which it was handed the objects that it allocated from the inner
+/// allocator.
+fn layouts(alloc: &'static ShufflingAllocator<Logging, 16>) -> Vec<Vec<usize>> {
+    // Initialize the allocator's shared state up front, rather than on
+    // whichever thread gets to it first.
+    alloc.seed();
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            thread::Builder::new()
+                .name(format!("worker-{}", i))
+                .spawn(move || unsafe {
+                    let layout = Layout::new::<u64>();
+                    let ptrs: Vec<_> = (0..64).map(|_| alloc.alloc(layout) as usize).collect();
+                    let log = LOG.with(|log| log.borrow().clone());
+                    ptrs.iter()
+                        .map(|p| log.iter().position(|q| q == p).unwrap())
+                        .collect()
+                })
+                .unwrap()
+        })
+        .collect();
+    threads.into_iter().map(|t| t.join().unwrap()).collect()
+}
+
+#[test]
+fn per_thread_layouts_are_reproducible() {
+    let layouts_1 = layouts(&RUN_1);
+    let layouts_2 = layouts(&RUN_2);
+    assert_eq!(layouts_1, layouts_2);
+
+    // Each thread still has its own streams.
+    assert_ne!(layouts_1[0], layouts_1[1]);
+}
+
+#[test]
+fn dropped_allocators_arrays_are_not_reused() {
+    /// Allocate from `alloc`, and get the order in which this thread was handed
+    /// the objects that it allocated from the inner allocator.
+    fn layout_of(alloc: &ShufflingAllocator<Logging, 16>) -> Vec<usize> {
+        LOG.with(|log| log.borrow_mut().clear());
+        let layout = Layout::new::<u64>();
+        let ptrs: Vec<_> = (0..64)
+            .map(|_| unsafe { alloc.alloc(layout) as usize })
+            .collect();
+        let log = LOG.with(|log| log.borrow().clone());
+        ptrs.iter()
+            .map(|p| log.iter().position(|q| q == p).unwrap())
+            .collect()
+    }
+
+    // Allocators that aren't `static`s may be built at the address of one
+    // that was dropped, but mustn't inherit its arrays. Boxing them unshuffled
+    // lets the inner allocator reuse the dropped one's address.
+    let run = |first_seed: Option<u64>| {
+        thread::Builder::new()
+            .name("reused".to_string())
+            .spawn(move || {
+                if let Some(seed) = first_seed {
+                    let first = unshuffled(|| {
+                        Box::new(
+                            ShufflingAllocator::<Logging, 16>::builder(&Logging)
+                                .seed(seed)
+                                .thread_local_arrays(true)
+                                .build(),
+                        )
+                    });
+                    layout_of(&first);
+                    unshuffled(|| drop(first));
+                }
+                let second = unshuffled(|| {
+                    Box::new(
+                        ShufflingAllocator::<Logging, 16>::builder(&Logging)
+                            .seed(3)
+                            .thread_local_arrays(true)
+                            .build(),
+                    )
+                });
+                let layout = layout_of(&second);
+                #[cfg(feature = "stats")]
+                {
+                    let stats = second.stats();
+                    let class = stats.classes().iter().find(|c| c.size == 8).unwrap();
+                    assert_eq!(class.allocs, 64);
+                }
+                unshuffled(|| drop(second));
+                layout
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    };
+    assert_eq!(run(Some(1)), run(None));
+}
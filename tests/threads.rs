@@ -0,0 +1,29 @@
+use shuffling_allocator::ShufflingAllocator;
+use std::{alloc::System, thread};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = shuffling_allocator::wrap!(&System);
+
+#[test]
+fn concurrent_allocation() {
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            thread::spawn(move || {
+                let mut boxes = Vec::new();
+                for i in 0..10_000usize {
+                    boxes.push(Box::new([t, i]));
+                    if i % 3 == 0 {
+                        boxes.swap_remove(i % boxes.len());
+                    }
+                }
+                for b in &boxes {
+                    assert_eq!(b[0], t);
+                }
+                boxes.len()
+            })
+        })
+        .collect();
+    for t in threads {
+        assert!(t.join().unwrap() > 0);
+    }
+}
@@ -0,0 +1,113 @@
+use shuffling_allocator::{unshuffled, ShufflingAllocator};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Wraps the system allocator, checking that everything is deallocated with the
+/// layout that it was allocated with, and remembering the last allocation.
+struct Checking {
+    live: Mutex<Vec<(usize, Layout)>>,
+    last: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Checking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        self.live.lock().unwrap().push((p as usize, layout));
+        self.last.store(p as usize, Ordering::SeqCst);
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut live = self.live.lock().unwrap();
+        let i = live.iter().position(|&(p, _)| p == ptr as usize).unwrap();
+        assert_eq!(live.swap_remove(i).1, layout);
+        System.dealloc(ptr, layout)
+    }
+}
+
+static CHECKING_1: Checking = Checking {
+    live: Mutex::new(Vec::new()),
+    last: AtomicUsize::new(0),
+};
+static CHECKING_2: Checking = Checking {
+    live: Mutex::new(Vec::new()),
+    last: AtomicUsize::new(0),
+};
+
+static TOGGLED: ShufflingAllocator<Checking> = ShufflingAllocator::builder(&CHECKING_1)
+    .large_object_pages(4)
+    .build();
+static SCOPED: ShufflingAllocator<Checking> = ShufflingAllocator::builder(&CHECKING_2)
+    .large_object_pages(4)
+    .build();
+
+const LAYOUTS: [Layout; 3] = [
+    // Shuffled in a size class.
+    unsafe { Layout::from_size_align_unchecked(24, 8) },
+    // Randomized at page granularity.
+    unsafe { Layout::from_size_align_unchecked(1 << 16, 8) },
+    // Never shuffled.
+    unsafe { Layout::from_size_align_unchecked(64, 1 << 13) },
+];
+
+/// Whether `alloc` hands out what its inner allocator just allocated, as it
+/// does when it isn't shuffling.
+fn passes_through(alloc: &ShufflingAllocator<Checking>, inner: &Checking) -> bool {
+    unsafe {
+        let p = alloc.alloc(LAYOUTS[0]);
+        let passed = p as usize == inner.last.load(Ordering::SeqCst);
+        alloc.dealloc(p, LAYOUTS[0]);
+        passed
+    }
+}
+
+#[test]
+fn set_enabled() {
+    assert!(TOGGLED.is_enabled());
+    assert!(!passes_through(&TOGGLED, &CHECKING_1));
+
+    unsafe {
+        // Allocate with shuffling on, and free with it off...
+        let ptrs: Vec<_> = LAYOUTS.iter().map(|&l| TOGGLED.alloc(l)).collect();
+        TOGGLED.set_enabled(false);
+        assert!(!TOGGLED.is_enabled());
+        assert!(passes_through(&TOGGLED, &CHECKING_1));
+        for (&p, &l) in ptrs.iter().zip(&LAYOUTS) {
+            TOGGLED.dealloc(p, l);
+        }
+
+        // ...and the other way around, growing the allocations while off.
+        let ptrs: Vec<_> = LAYOUTS.iter().map(|&l| TOGGLED.alloc(l)).collect();
+        let ptrs: Vec<_> = ptrs
+            .iter()
+            .zip(&LAYOUTS)
+            .map(|(&p, &l)| TOGGLED.realloc(p, l, l.size() + 8))
+            .collect();
+        TOGGLED.set_enabled(true);
+        for (&p, &l) in ptrs.iter().zip(&LAYOUTS) {
+            let l = Layout::from_size_align(l.size() + 8, l.align()).unwrap();
+            TOGGLED.dealloc(p, l);
+        }
+    }
+    assert!(!passes_through(&TOGGLED, &CHECKING_1));
+}
+
+#[test]
+fn unshuffled_scope() {
+    assert!(!passes_through(&SCOPED, &CHECKING_2));
+    let ptrs: Vec<_> = LAYOUTS
+        .iter()
+        .map(|&l| unsafe { SCOPED.alloc(l) })
+        .collect();
+
+    unshuffled(|| {
+        assert!(passes_through(&SCOPED, &CHECKING_2));
+        for (&p, &l) in ptrs.iter().zip(&LAYOUTS) {
+            unsafe { SCOPED.dealloc(p, l) };
+        }
+    });
+
+    assert!(!passes_through(&SCOPED, &CHECKING_2));
+    assert!(SCOPED.is_enabled());
+}
@@ -0,0 +1,38 @@
+mod bump;
+
+use shuffling_allocator::with_seed;
+use std::panic;
+
+// Scoped seeds apply to every shuffling allocator on the thread, so don't use
+// one as the global allocator here: the test's own allocations, like the
+// panic below, would draw from the scoped streams too.
+
+bump::seeded! {
+    SEEDED_1 = 1,
+    SEEDED_2 = 2,
+    SEEDED_3 = 3,
+    SEEDED_4 = 4,
+}
+
+#[test]
+fn scoped_seed() {
+    // Different allocator seeds, but the same scoped seed.
+    let layout_1 = with_seed(7, || SEEDED_1.layout());
+    let layout_2 = with_seed(7, || SEEDED_2.layout());
+    assert_eq!(layout_1, layout_2);
+
+    // A nested scope doesn't disturb the outer scope's stream, even if it
+    // panics.
+    let layout_3 = with_seed(8, || {
+        let _ = panic::catch_unwind(|| {
+            with_seed(9, || {
+                SEEDED_3.layout();
+                panic!();
+            })
+        });
+        SEEDED_3.layout()
+    });
+    with_seed(9, || SEEDED_4.layout());
+    let layout_4 = with_seed(8, || SEEDED_4.layout());
+    assert_eq!(layout_3, layout_4);
+}
@@ -0,0 +1,89 @@
+use shuffling_allocator::ShufflingAllocator;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[global_allocator]
+static A: ShufflingAllocator<System> = ShufflingAllocator::new(&System);
+
+/// Wraps the system allocator and counts calls to `alloc_zeroed`.
+struct Counting {
+    zeroed: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.zeroed.fetch_add(1, Ordering::SeqCst);
+        System.alloc_zeroed(layout)
+    }
+}
+
+static COUNTING_1: Counting = Counting {
+    zeroed: AtomicUsize::new(0),
+};
+static COUNTING_2: Counting = Counting {
+    zeroed: AtomicUsize::new(0),
+};
+
+static PASS_THROUGH: ShufflingAllocator<Counting> = ShufflingAllocator::new(&COUNTING_1);
+static LARGE: ShufflingAllocator<Counting> = ShufflingAllocator::builder(&COUNTING_2)
+    .large_object_pages(4)
+    .build();
+
+unsafe fn check_zeroed(p: *mut u8, len: usize) {
+    assert!(!p.is_null());
+    assert!(std::slice::from_raw_parts(p, len).iter().all(|&b| b == 0));
+}
+
+#[test]
+fn zeroed_vecs() {
+    for _ in 0..100 {
+        let mut dirty = vec![0xff_u8; 100];
+        dirty[0] = 1;
+        drop(dirty);
+        let zeroed: Vec<u8> = vec![0; 100];
+        assert!(zeroed.iter().all(|&b| b == 0));
+    }
+}
+
+#[test]
+fn shuffled_entries_are_zeroed() {
+    let layout = Layout::from_size_align(48, 8).unwrap();
+    unsafe {
+        // Dirty every entry of the shuffling array.
+        for _ in 0..1024 {
+            let p = PASS_THROUGH.alloc(layout);
+            p.write_bytes(0xff, layout.size());
+            PASS_THROUGH.dealloc(p, layout);
+        }
+        for _ in 0..1024 {
+            let p = PASS_THROUGH.alloc_zeroed(layout);
+            check_zeroed(p, layout.size());
+            p.write_bytes(0xff, layout.size());
+            PASS_THROUGH.dealloc(p, layout);
+        }
+    }
+}
+
+#[test]
+fn unshuffled_uses_inner_alloc_zeroed() {
+    let layout = Layout::from_size_align(1 << 20, 8).unwrap();
+    unsafe {
+        let p = PASS_THROUGH.alloc_zeroed(layout);
+        check_zeroed(p, layout.size());
+        PASS_THROUGH.dealloc(p, layout);
+
+        let p = LARGE.alloc_zeroed(layout);
+        check_zeroed(p, layout.size());
+        LARGE.dealloc(p, layout);
+    }
+    assert_eq!(COUNTING_1.zeroed.load(Ordering::SeqCst), 1);
+    assert_eq!(COUNTING_2.zeroed.load(Ordering::SeqCst), 1);
+}